@@ -0,0 +1,69 @@
+use common::Candle;
+use core::providers::binance;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use serde_json::Value;
+use std::hint::black_box;
+
+// Frames recorded from the Binance combined stream
+const FRAMES: &str = include_str!("data/binance_frames.jsonl");
+
+// The previous implementation, based on a dynamic serde_json::Value
+// Kept here as the reference for the typed parser
+fn parse_message_value(message: &str) -> Option<Candle> {
+    let parsed: Value = serde_json::from_str(message).ok()?;
+    let data = &parsed["data"];
+    let kline = &data["k"];
+
+    let open_time = kline["t"].as_i64()?;
+    let close_time = kline["T"].as_i64()?;
+    let symbol = kline["s"].as_str()?.to_string();
+    let timerange = kline["i"].as_str()?.to_string();
+    let open = kline["o"].as_str()?.parse::<f64>().ok()?;
+    let price = kline["c"].as_str()?.parse::<f64>().ok()?;
+    let high = kline["h"].as_str()?.parse::<f64>().ok()?;
+    let low = kline["l"].as_str()?.parse::<f64>().ok()?;
+    let volume = kline["v"].as_str()?.parse::<f64>().ok()?;
+    let usdt_volume = volume * price;
+
+    Some(Candle {
+        open_time,
+        close_time,
+        symbol,
+        timerange,
+        open,
+        close: None,
+        high,
+        low,
+        price: price.into(),
+        volume,
+        usdt_volume
+    })
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let frames = FRAMES.lines().filter(|l| !l.is_empty()).collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("binance_parse_message");
+    group.throughput(Throughput::Elements(frames.len() as u64));
+
+    group.bench_function("value", |b| {
+        b.iter(|| {
+            for frame in &frames {
+                black_box(parse_message_value(black_box(frame)));
+            }
+        })
+    });
+
+    group.bench_function("typed", |b| {
+        b.iter(|| {
+            for frame in &frames {
+                let _ = black_box(binance::parse_message(black_box(frame), false));
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
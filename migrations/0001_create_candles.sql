@@ -0,0 +1,16 @@
+-- Candles of every symbol and timerange
+-- NUMERIC fits both modes, the queries cast the values to float8 or numeric
+CREATE TABLE IF NOT EXISTS candles (
+    symbol TEXT NOT NULL,
+    timerange TEXT NOT NULL,
+    open_time TIMESTAMPTZ NOT NULL,
+    close_time TIMESTAMPTZ NOT NULL,
+    open NUMERIC NOT NULL,
+    high NUMERIC NOT NULL,
+    low NUMERIC NOT NULL,
+    close NUMERIC,
+    volume NUMERIC NOT NULL,
+    usdt_volume NUMERIC NOT NULL,
+    -- Also serves the backward scans of the warmup (last candle of each symbol and timerange)
+    PRIMARY KEY (symbol, timerange, open_time)
+);
@@ -0,0 +1,2 @@
+-- Candles saved before they are closed (e.g. at shutdown)
+ALTER TABLE candles ADD COLUMN IF NOT EXISTS partial BOOLEAN NOT NULL DEFAULT false;
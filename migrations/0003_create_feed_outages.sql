@@ -0,0 +1,9 @@
+-- Intervals without updates for a symbol, to be backfilled
+CREATE TABLE IF NOT EXISTS feed_outages (
+    id BIGSERIAL PRIMARY KEY,
+    symbol TEXT NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL,
+    ended_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS feed_outages_symbol_idx ON feed_outages (symbol, started_at);
@@ -0,0 +1,12 @@
+-- Candles rejected by the validation, kept for inspection
+CREATE TABLE IF NOT EXISTS quarantined_candles (
+    id BIGSERIAL PRIMARY KEY,
+    symbol TEXT NOT NULL,
+    timerange TEXT NOT NULL,
+    open_time TIMESTAMPTZ NOT NULL,
+    rule TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS quarantined_candles_symbol_idx ON quarantined_candles (symbol, open_time);
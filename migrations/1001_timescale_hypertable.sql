@@ -0,0 +1,12 @@
+-- TimescaleDB mode: the 1m candles are stored in a hypertable
+-- The other timeranges are derived from them by continuous aggregates (1002)
+CREATE EXTENSION IF NOT EXISTS timescaledb;
+
+SELECT create_hypertable('candles', 'open_time', if_not_exists => TRUE, migrate_data => TRUE);
+
+-- The compression policy itself is configured at startup ([database] compress_after_days)
+ALTER TABLE candles SET (
+    timescaledb.compress,
+    timescaledb.compress_segmentby = 'symbol, timerange',
+    timescaledb.compress_orderby = 'open_time DESC'
+);
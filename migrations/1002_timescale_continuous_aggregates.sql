@@ -0,0 +1,128 @@
+-- Continuous aggregates of the 1m candles, one per timerange
+-- Not transactional: each statement is run on its own, so no semicolon in the comments or strings
+-- The recent candles are computed on the fly (materialized_only = false), the older ones are materialized by the policies
+
+CREATE MATERIALIZED VIEW IF NOT EXISTS candles_5m
+WITH (timescaledb.continuous, timescaledb.materialized_only = false) AS
+SELECT symbol,
+    time_bucket(INTERVAL '5 minutes', open_time) AS bucket,
+    first(open, open_time) AS open,
+    max(high) AS high,
+    min(low) AS low,
+    last(close, open_time) AS close,
+    sum(volume) AS volume,
+    sum(usdt_volume) AS usdt_volume,
+    bool_or(partial) AS partial
+FROM candles
+WHERE timerange = '1m'
+GROUP BY symbol, time_bucket(INTERVAL '5 minutes', open_time)
+WITH NO DATA;
+
+SELECT add_continuous_aggregate_policy('candles_5m', start_offset => INTERVAL '1 day', end_offset => INTERVAL '5 minutes', schedule_interval => INTERVAL '5 minutes', if_not_exists => TRUE);
+
+CREATE MATERIALIZED VIEW IF NOT EXISTS candles_15m
+WITH (timescaledb.continuous, timescaledb.materialized_only = false) AS
+SELECT symbol,
+    time_bucket(INTERVAL '15 minutes', open_time) AS bucket,
+    first(open, open_time) AS open,
+    max(high) AS high,
+    min(low) AS low,
+    last(close, open_time) AS close,
+    sum(volume) AS volume,
+    sum(usdt_volume) AS usdt_volume,
+    bool_or(partial) AS partial
+FROM candles
+WHERE timerange = '1m'
+GROUP BY symbol, time_bucket(INTERVAL '15 minutes', open_time)
+WITH NO DATA;
+
+SELECT add_continuous_aggregate_policy('candles_15m', start_offset => INTERVAL '1 day', end_offset => INTERVAL '15 minutes', schedule_interval => INTERVAL '15 minutes', if_not_exists => TRUE);
+
+CREATE MATERIALIZED VIEW IF NOT EXISTS candles_30m
+WITH (timescaledb.continuous, timescaledb.materialized_only = false) AS
+SELECT symbol,
+    time_bucket(INTERVAL '30 minutes', open_time) AS bucket,
+    first(open, open_time) AS open,
+    max(high) AS high,
+    min(low) AS low,
+    last(close, open_time) AS close,
+    sum(volume) AS volume,
+    sum(usdt_volume) AS usdt_volume,
+    bool_or(partial) AS partial
+FROM candles
+WHERE timerange = '1m'
+GROUP BY symbol, time_bucket(INTERVAL '30 minutes', open_time)
+WITH NO DATA;
+
+SELECT add_continuous_aggregate_policy('candles_30m', start_offset => INTERVAL '1 day', end_offset => INTERVAL '30 minutes', schedule_interval => INTERVAL '30 minutes', if_not_exists => TRUE);
+
+CREATE MATERIALIZED VIEW IF NOT EXISTS candles_1h
+WITH (timescaledb.continuous, timescaledb.materialized_only = false) AS
+SELECT symbol,
+    time_bucket(INTERVAL '1 hour', open_time) AS bucket,
+    first(open, open_time) AS open,
+    max(high) AS high,
+    min(low) AS low,
+    last(close, open_time) AS close,
+    sum(volume) AS volume,
+    sum(usdt_volume) AS usdt_volume,
+    bool_or(partial) AS partial
+FROM candles
+WHERE timerange = '1m'
+GROUP BY symbol, time_bucket(INTERVAL '1 hour', open_time)
+WITH NO DATA;
+
+SELECT add_continuous_aggregate_policy('candles_1h', start_offset => INTERVAL '3 days', end_offset => INTERVAL '1 hour', schedule_interval => INTERVAL '1 hour', if_not_exists => TRUE);
+
+CREATE MATERIALIZED VIEW IF NOT EXISTS candles_4h
+WITH (timescaledb.continuous, timescaledb.materialized_only = false) AS
+SELECT symbol,
+    time_bucket(INTERVAL '4 hours', open_time) AS bucket,
+    first(open, open_time) AS open,
+    max(high) AS high,
+    min(low) AS low,
+    last(close, open_time) AS close,
+    sum(volume) AS volume,
+    sum(usdt_volume) AS usdt_volume,
+    bool_or(partial) AS partial
+FROM candles
+WHERE timerange = '1m'
+GROUP BY symbol, time_bucket(INTERVAL '4 hours', open_time)
+WITH NO DATA;
+
+SELECT add_continuous_aggregate_policy('candles_4h', start_offset => INTERVAL '7 days', end_offset => INTERVAL '4 hours', schedule_interval => INTERVAL '1 hour', if_not_exists => TRUE);
+
+CREATE MATERIALIZED VIEW IF NOT EXISTS candles_1d
+WITH (timescaledb.continuous, timescaledb.materialized_only = false) AS
+SELECT symbol,
+    time_bucket(INTERVAL '1 day', open_time) AS bucket,
+    first(open, open_time) AS open,
+    max(high) AS high,
+    min(low) AS low,
+    last(close, open_time) AS close,
+    sum(volume) AS volume,
+    sum(usdt_volume) AS usdt_volume,
+    bool_or(partial) AS partial
+FROM candles
+WHERE timerange = '1m'
+GROUP BY symbol, time_bucket(INTERVAL '1 day', open_time)
+WITH NO DATA;
+
+SELECT add_continuous_aggregate_policy('candles_1d', start_offset => INTERVAL '7 days', end_offset => INTERVAL '1 day', schedule_interval => INTERVAL '1 hour', if_not_exists => TRUE);
+
+-- Every timerange with the columns of the candles table
+-- The 1m rows of the hypertable, the others from their continuous aggregate
+CREATE OR REPLACE VIEW candle_series AS
+SELECT symbol, timerange, open_time, close_time, open, high, low, close, volume, usdt_volume, partial FROM candles WHERE timerange = '1m'
+UNION ALL
+SELECT symbol, '5m'::text, bucket, bucket + INTERVAL '5 minutes' - INTERVAL '1 second', open, high, low, close, volume, usdt_volume, partial FROM candles_5m
+UNION ALL
+SELECT symbol, '15m'::text, bucket, bucket + INTERVAL '15 minutes' - INTERVAL '1 second', open, high, low, close, volume, usdt_volume, partial FROM candles_15m
+UNION ALL
+SELECT symbol, '30m'::text, bucket, bucket + INTERVAL '30 minutes' - INTERVAL '1 second', open, high, low, close, volume, usdt_volume, partial FROM candles_30m
+UNION ALL
+SELECT symbol, '1h'::text, bucket, bucket + INTERVAL '1 hour' - INTERVAL '1 second', open, high, low, close, volume, usdt_volume, partial FROM candles_1h
+UNION ALL
+SELECT symbol, '4h'::text, bucket, bucket + INTERVAL '4 hours' - INTERVAL '1 second', open, high, low, close, volume, usdt_volume, partial FROM candles_4h
+UNION ALL
+SELECT symbol, '1d'::text, bucket, bucket + INTERVAL '1 day' - INTERVAL '1 second', open, high, low, close, volume, usdt_volume, partial FROM candles_1d;
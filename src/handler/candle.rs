@@ -1,24 +1,52 @@
 use common::{Candle, TIMERANGES};
 use tokio::sync::Mutex;
-use crate::server::{database::add_candle, websocket::send_message_to_clients};
-
-use once_cell::sync::Lazy;
-use serde_json::{Map, Value};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use crate::CONFIG;
+use crate::handler::decimal::{EXACT_KEY, ExactState, ExactValues};
+use crate::handler::history::HISTORY;
+use crate::handler::validation::{Rule, validate_candle};
+use crate::server::{database::{add_candle, add_partial_candle, quarantine_candle}, watchdog, websocket::{candle_message, send_message_to_subscribers}};
+use crate::utils::config::ValidationMode;
+use crate::utils::metrics::METRICS;
+
+use once_cell::sync::{Lazy, OnceCell};
 use std::collections::HashMap;
+use tracing::{debug, error, info, instrument, warn};
 
 pub enum CandleOrValue {
     Candle(Candle),
     Value(f64),
+    // Exact values of the candles (decimal mode only)
+    Exact(ExactState),
 }
 
 // Store all the candles of each symbol in a hashmap
 // The key is the symbol and the value is the actual
 pub static CANDLES: Lazy<Mutex<HashMap<String, HashMap<String, CandleOrValue>>>> = Lazy::new(|| {Mutex::new(HashMap::new())});
 
+// Receives a copy of every closed candle (e.g. for the replay output)
+// With its exact values in decimal mode
+static CLOSED_TAP: OnceCell<UnboundedSender<(Candle, Option<ExactValues>)>> = OnceCell::new();
+
+// Get the closed candles as they are produced
+// Only one receiver can exist
+pub fn tap_closed_candles() -> Option<UnboundedReceiver<(Candle, Option<ExactValues>)>> {
+    let (sender, receiver) = unbounded_channel();
+    CLOSED_TAP.set(sender).ok()?;
+
+    Some(receiver)
+}
+
 // When we receive a new candle,
 // Either update the candle data (if it's the same)
 // Or either send the old candle to the db and load the new candle into the hashmap
-pub async fn proceed_data(new_candle: Candle) {
+// In decimal mode, the exact values of the candle are aggregated alongside
+#[instrument(level = "debug", skip_all, fields(symbol = %new_candle.symbol))]
+pub async fn proceed_data(new_candle: Candle, new_exact: Option<ExactValues>) {
+    // Load the validation rules
+    // Before locking the candles, the config reload locks them the other way around
+    let validation = CONFIG.get().unwrap().lock().await.validation.clone();
+
     // Load the last candles from the hashmap
     let mut last_candles = CANDLES.lock().await;
 
@@ -39,7 +67,28 @@ pub async fn proceed_data(new_candle: Candle) {
 
     // Load all the candles from the symbol
     // So we can update each timerange 
-    let last_candles = last_candles.get_mut(&new_candle.symbol).unwrap();
+    // A symbol removed from the config can still tick until the provider reconnects
+    let Some(last_candles) = last_candles.get_mut(&new_candle.symbol) else {
+        debug!(symbol = %new_candle.symbol, "Tick for an unregistered symbol");
+        return;
+    };
+
+    // Check the quality of the candle before aggregating it
+    // Compared to the last price of the symbol
+    if validation.enabled {
+        let previous_price = match last_candles.get("1m") {
+            Some(CandleOrValue::Candle(candle)) if candle.open_time != 0 => candle.price,
+            _ => None,
+        };
+
+        if let Err(rule) = validate_candle(&new_candle, previous_price, &validation) {
+            reject_candle(new_candle, rule, validation.mode);
+            return;
+        }
+    }
+
+    METRICS.tick(&new_candle.symbol);
+    watchdog::record_tick(&new_candle.symbol);
 
     // Load the last volume and usdt volume
     let mut previous_volume = match last_candles.get("volume") {
@@ -51,6 +100,16 @@ pub async fn proceed_data(new_candle: Candle) {
         _ => 0.0, 
     };
 
+    // Take the exact values out of the map while we update the candles
+    let mut exact = new_exact.map(|new_exact| {
+        let state = match last_candles.remove(EXACT_KEY) {
+            Some(CandleOrValue::Exact(state)) => state,
+            _ => ExactState::default(),
+        };
+
+        (state, new_exact)
+    });
+
     for timerange in timeranges.iter() {
         // Get the last candle for the timerange
         if let Some(CandleOrValue::Candle(last_candle)) = last_candles.get_mut(timerange) {
@@ -76,6 +135,13 @@ pub async fn proceed_data(new_candle: Candle) {
                     previous_volume = 0.0;
                     previous_usdt_volume = 0.0;
                 }
+
+                if let Some((state, new_exact)) = exact.as_mut() {
+                    if timerange == "1m" {
+                        state.start_volume(new_exact);
+                    }
+                    state.open(timerange, new_exact);
+                }
             } else if new_candle.open_time >= last_candle.open_time && new_candle.open_time < last_candle.close_time {
                 // If the candle is in the same time range, we just update it
                 // The open time and price are the same
@@ -95,12 +161,20 @@ pub async fn proceed_data(new_candle: Candle) {
 
                 last_candle.volume += volume_to_add;
                 last_candle.usdt_volume += usdt_volume_to_add;
+
+                if let Some((state, new_exact)) = exact.as_mut() {
+                    if timerange == "1m" {
+                        state.update_volume(new_exact);
+                    }
+                    state.update(timerange, new_exact);
+                }
             } else {
                 // If the candle is not the same as the past one
                 // We just create a new candle, and send the old one to the db
-                if new_candle.open_time - last_candle.close_time > 1_0000 {
+                let continuous = new_candle.open_time - last_candle.close_time <= 1_0000;
+                if !continuous {
                     // If we notice a gap between the two candles we notify it
-                    println!("Candle is not continuous: {:?} {:?}", new_candle, last_candle);
+                    warn!(symbol = %new_candle.symbol, timerange = %timerange, last_close_time = last_candle.close_time, new_open_time = new_candle.open_time, "Candle is not continuous");
                 } else {
                     // Before sending the new candle, we need to update the last candle
                     // The close time is the open time of the new candle
@@ -116,15 +190,28 @@ pub async fn proceed_data(new_candle: Candle) {
                     previous_usdt_volume = 0.0;
                 }
 
-                // Update the volume and usdt volume
-                last_candle.volume = volume_to_add;
-                last_candle.usdt_volume = usdt_volume_to_add;
+                // Same thing for the exact values
+                // The new exact candle is started at the same time
+                let closed_exact = exact.as_mut().and_then(|(state, new_exact)| {
+                    if timerange == "1m" {
+                        state.start_volume(new_exact);
+                    }
+                    state.close(timerange, new_exact, continuous)
+                });
+
+                METRICS.candles_closed.inc(timerange);
+                if let Some(tap) = CLOSED_TAP.get() {
+                    let _ = tap.send((last_candle.clone(), closed_exact.clone()));
+                }
 
                 // Send the last candle to the websocket
                 send_candle(&last_candle).await;
 
                 // Send the last candle to the db
-                add_candle(&last_candle.clone()).await;
+                add_candle(&last_candle.clone(), closed_exact.as_ref()).await;
+
+                // Keep it in the history of the symbol
+                HISTORY.lock().await.push(last_candle.clone());
 
                 // Update the last candle with the new one
                 *last_candle = new_candle.clone();
@@ -135,6 +222,10 @@ pub async fn proceed_data(new_candle: Candle) {
                 let (open_time, close_time) = get_timerange(&timerange, new_candle.open_time);
                 last_candle.open_time = open_time;
                 last_candle.close_time = close_time;
+
+                // The new candle starts with the volume of this tick
+                last_candle.volume = volume_to_add;
+                last_candle.usdt_volume = usdt_volume_to_add;
             }
 
             // Send the new candle to the websocket
@@ -151,20 +242,141 @@ pub async fn proceed_data(new_candle: Candle) {
         *v = CandleOrValue::Value(previous_usdt_volume + usdt_volume_to_add);
     });
 
+    // And put the exact values back
+    if let Some((mut state, _)) = exact {
+        state.commit_volume();
+        last_candles.insert(EXACT_KEY.to_string(), CandleOrValue::Exact(state));
+    }
+
 }
 
-pub async fn send_candle(candle: &Candle) {
-    let mut data = Map::new();
+// Drop a candle that failed the validation
+// And keep it in the quarantine table if asked to
+fn reject_candle(candle: Candle, rule: Rule, mode: ValidationMode) {
+    METRICS.validation_failures.inc(rule.as_str());
+    warn!(symbol = %candle.symbol, %rule, open_time = candle.open_time, "Candle rejected");
+
+    if mode == ValidationMode::Quarantine {
+        // Don't block the aggregation on the database
+        tokio::spawn(async move {
+            if let Err(e) = quarantine_candle(&candle, rule).await {
+                error!(symbol = %candle.symbol, error = %e, "Unable to quarantine the candle");
+            }
+        });
+    }
+}
+
+// Create the empty entries of a symbol (one per timerange + the live volumes)
+pub fn empty_entries(timeranges: &[String]) -> HashMap<String, CandleOrValue> {
+    let mut map = timeranges
+        .iter()
+        .map(|t| (t.clone(), CandleOrValue::Candle(Candle::default())))
+        .collect::<HashMap<_, _>>();
+
+    map.insert("volume".to_string(), CandleOrValue::Value(0.0));
+    map.insert("usdt_volume".to_string(), CandleOrValue::Value(0.0));
+
+    map
+}
+
+// Make sure every symbol has an entry for every timerange
+// Existing candles are kept untouched
+pub async fn register_symbols(symbols: &[String], timeranges: &[String]) {
+    let mut candles = CANDLES.lock().await;
+
+    for symbol in symbols {
+        let entries = candles.entry(symbol.clone()).or_insert_with(|| empty_entries(timeranges));
+
+        for timerange in timeranges {
+            entries.entry(timerange.clone()).or_insert_with(|| CandleOrValue::Candle(Candle::default()));
+        }
+    }
+}
+
+// Stop aggregating the symbols and timeranges removed from the config
+// Their candles in progress are saved as partial, like at shutdown
+pub async fn unregister_symbols(symbols: &[String], timeranges: &[String]) {
+    let mut in_progress = Vec::new();
+
+    {
+        let mut candles = CANDLES.lock().await;
+
+        for symbol in symbols {
+            if let Some(entries) = candles.remove(symbol) {
+                in_progress.extend(in_progress_candles(&entries, None));
+            }
+        }
+
+        for entries in candles.values_mut() {
+            in_progress.extend(in_progress_candles(entries, Some(timeranges)));
+
+            let exact = match entries.get_mut(EXACT_KEY) {
+                Some(CandleOrValue::Exact(state)) => Some(state),
+                _ => None,
+            };
+            if let Some(state) = exact {
+                for timerange in timeranges {
+                    state.candles.remove(timerange);
+                }
+            }
+
+            for timerange in timeranges {
+                entries.remove(timerange);
+            }
+        }
+    }
 
+    // Saved once the aggregation is released
+    for (candle, exact) in &in_progress {
+        add_partial_candle(candle, exact.as_ref()).await;
+    }
+    if !in_progress.is_empty() {
+        info!(saved = in_progress.len(), "Saved the candles of the removed symbols and timeranges");
+    }
+}
+
+// The started candles of a symbol, with their exact values
+// Only the given timeranges if any
+fn in_progress_candles(entries: &HashMap<String, CandleOrValue>, timeranges: Option<&[String]>) -> Vec<(Candle, Option<ExactValues>)> {
+    let exact = match entries.get(EXACT_KEY) {
+        Some(CandleOrValue::Exact(state)) => Some(state),
+        _ => None,
+    };
+
+    entries.iter()
+        .filter(|(timerange, _)| timeranges.is_none_or(|timeranges| timeranges.contains(timerange)))
+        .filter_map(|(timerange, value)| match value {
+            CandleOrValue::Candle(candle) if candle.open_time != 0 => {
+                Some((candle.clone(), exact.and_then(|state| state.candles.get(timerange)).cloned()))
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+#[instrument(level = "debug", skip_all, fields(symbol = %candle.symbol, timerange = %candle.timerange))]
+pub async fn send_candle(candle: &Candle) {
     // Structure the data to send
-    data.insert("type".to_string(), Value::String("candle".to_string()));
-    data.insert("value".to_string(), serde_json::to_value(candle).unwrap());
+    // Each session numbers it when it is sent
+    let json_data = candle_message("candle", candle);
 
-    // Convert the data to a JSON string
-    let json_data = Value::Object(data).to_string();
+    // Send the data to the clients of the stream
+    send_message_to_subscribers(&candle.symbol, &candle.timerange, &json_data).await;
+}
 
-    // Send the data to the clients
-    send_message_to_clients(&json_data).await;
+// Duration of a timerange in milliseconds
+// None if the timerange is not supported
+pub fn timerange_duration_ms(timerange: &str) -> Option<i64> {
+    match timerange {
+        "1m" => Some(60_000),
+        "5m" => Some(5 * 60_000),
+        "15m" => Some(15 * 60_000),
+        "30m" => Some(30 * 60_000),
+        "1h" => Some(60 * 60_000),
+        "4h" => Some(4 * 60 * 60_000),
+        "1d" => Some(24 * 60 * 60_000),
+        _ => None,
+    }
 }
 
 // This function will determine the open time and close time of the candle
@@ -172,16 +384,10 @@ pub async fn send_candle(candle: &Candle) {
 // because we can't create timeranges on the fly we need to calculate the open and close time
 // Actual time is the current time in nanoseconds
 pub fn get_timerange(timerange: &str, actual_time_ms: i64) -> (i64, i64) {
-    let duration_ms = match timerange {
-        "1m" => 60_000,
-        "5m" => 5 * 60_000,
-        "15m" => 15 * 60_000,
-        "30m" => 30 * 60_000,
-        "1h" => 60 * 60_000,
-        "4h" => 4 * 60 * 60_000,
-        "1d" => 24 * 60 * 60_000,
-        _ => {
-            eprintln!("Unknown timerange: {}", timerange);
+    let duration_ms = match timerange_duration_ms(timerange) {
+        Some(duration_ms) => duration_ms,
+        None => {
+            error!(timerange, "Unknown timerange");
             return (0, 0);
         }
     };
@@ -192,4 +398,237 @@ pub fn get_timerange(timerange: &str, actual_time_ms: i64) -> (i64, i64) {
     // We subtract 1 second to avoid having the same open and close time
     let close_time = open_time + duration_ms - 1_000;
     (open_time, close_time)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::database::{Backfill, rebuild_candles, set_store, store, write_pending};
+    use crate::server::store::{StoredCandle, memory::MemoryStore};
+    use crate::utils::config::Config;
+
+    use futures_util::StreamExt;
+    use std::sync::Arc;
+
+    // 2024-01-01 00:00:00 UTC
+    const START: i64 = 1_704_067_200_000;
+    const MINUTE: i64 = 60_000;
+
+    // The globals are shared by the tests, each one uses its own symbol
+    async fn setup(symbol: &str) {
+        static SETUP: tokio::sync::OnceCell<()> = tokio::sync::OnceCell::const_new();
+
+        SETUP.get_or_init(|| async {
+            let mut config = Config::default();
+            config.validation.enabled = false;
+            let _ = CONFIG.set(Arc::new(Mutex::new(config)));
+
+            *TIMERANGES.lock().await = vec!["1m".to_string(), "5m".to_string(), "1h".to_string()];
+            set_store(Arc::new(MemoryStore::default()));
+            HISTORY.lock().await.set_capacity(100);
+        }).await;
+
+        let timeranges = TIMERANGES.lock().await.clone();
+        register_symbols(&[symbol.to_string()], &timeranges).await;
+    }
+
+    // A tick of the 1m kline opened at `minute`, with the volume of the minute so far
+    fn tick(symbol: &str, minute: i64, open: f64, price: f64, volume: f64) -> Candle {
+        Candle {
+            open_time: START + minute * MINUTE,
+            close_time: START + minute * MINUTE + MINUTE - 1,
+            symbol: symbol.to_string(),
+            timerange: "1m".to_string(),
+            open,
+            close: None,
+            high: open.max(price),
+            low: open.min(price),
+            price: Some(price),
+            volume,
+            usdt_volume: volume * price,
+        }
+    }
+
+    async fn current(symbol: &str, timerange: &str) -> Candle {
+        match CANDLES.lock().await.get(symbol).and_then(|entries| entries.get(timerange)) {
+            Some(CandleOrValue::Candle(candle)) => candle.clone(),
+            _ => panic!("no candle for {} {}", symbol, timerange),
+        }
+    }
+
+    async fn stored(symbol: &str, timerange: &str) -> Vec<StoredCandle> {
+        // The writes are queued
+        write_pending().await;
+
+        let candles = store().range(symbol, timerange, None, None).await.unwrap();
+        candles.map(|candle| candle.unwrap()).collect().await
+    }
+
+    #[tokio::test]
+    async fn new_minute_closes_the_candle() {
+        setup("ROLLUSDT").await;
+
+        proceed_data(tick("ROLLUSDT", 0, 100.0, 101.0, 1.0), None).await;
+        proceed_data(tick("ROLLUSDT", 0, 100.0, 99.0, 2.0), None).await;
+        proceed_data(tick("ROLLUSDT", 1, 99.5, 102.0, 1.0), None).await;
+
+        // The first minute is saved with its last price
+        let closed = stored("ROLLUSDT", "1m").await;
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].open_time, START);
+        assert_eq!((closed[0].open, closed[0].high, closed[0].low, closed[0].close), (100.0, 101.0, 99.0, 99.0));
+        assert!(!closed[0].partial);
+
+        // And closed with the open of the next one
+        let history = HISTORY.lock().await.get("ROLLUSDT", "1m", None);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].close, Some(99.5));
+
+        // The next one is in progress
+        let minute = current("ROLLUSDT", "1m").await;
+        assert_eq!(minute.open_time, START + MINUTE);
+        assert_eq!(minute.open, 99.5);
+
+        // The larger timeranges go on with both minutes
+        let five = current("ROLLUSDT", "5m").await;
+        assert_eq!((five.open_time, five.close_time), (START, START + 5 * MINUTE - 1_000));
+        assert_eq!((five.open, five.high, five.low, five.price), (100.0, 102.0, 99.0, Some(102.0)));
+        assert!(stored("ROLLUSDT", "5m").await.is_empty());
+        assert_eq!(current("ROLLUSDT", "1h").await.open_time, START);
+    }
+
+    #[tokio::test]
+    async fn volumes_add_the_difference_of_each_tick() {
+        setup("VOLUSDT").await;
+
+        // The kline volume is the one of the minute so far
+        proceed_data(tick("VOLUSDT", 0, 10.0, 10.0, 1.0), None).await;
+        proceed_data(tick("VOLUSDT", 0, 10.0, 10.0, 3.0), None).await;
+        proceed_data(tick("VOLUSDT", 0, 10.0, 10.0, 4.5), None).await;
+        assert_eq!(current("VOLUSDT", "1m").await.volume, 4.5);
+        assert_eq!(current("VOLUSDT", "5m").await.volume, 4.5);
+
+        // A new minute starts from its own volume
+        proceed_data(tick("VOLUSDT", 1, 10.0, 10.0, 2.0), None).await;
+        proceed_data(tick("VOLUSDT", 1, 10.0, 10.0, 2.5), None).await;
+
+        assert_eq!(stored("VOLUSDT", "1m").await[0].volume, 4.5);
+        assert_eq!(current("VOLUSDT", "1m").await.volume, 2.5);
+        assert_eq!(current("VOLUSDT", "5m").await.volume, 7.0);
+        assert_eq!(current("VOLUSDT", "1h").await.volume, 7.0);
+        assert_eq!(current("VOLUSDT", "1h").await.usdt_volume, 70.0);
+    }
+
+    #[tokio::test]
+    async fn closed_candles_keep_their_own_volume() {
+        setup("KEEPUSDT").await;
+
+        for minute in 0..5 {
+            proceed_data(tick("KEEPUSDT", minute, 10.0, 10.0, 2.0), None).await;
+        }
+        // The first tick of the next bucket closes the 5m candle
+        proceed_data(tick("KEEPUSDT", 5, 10.0, 10.0, 0.5), None).await;
+
+        let minutes = stored("KEEPUSDT", "1m").await;
+        assert_eq!(minutes.iter().map(|candle| candle.volume).collect::<Vec<_>>(), vec![2.0; 5]);
+
+        let five = stored("KEEPUSDT", "5m").await;
+        assert_eq!(five.len(), 1);
+        assert_eq!((five[0].volume, five[0].usdt_volume), (10.0, 100.0));
+        assert_eq!(HISTORY.lock().await.get("KEEPUSDT", "5m", None)[0].volume, 10.0);
+
+        // The next candles start with the volume of that tick
+        assert_eq!(current("KEEPUSDT", "1m").await.volume, 0.5);
+        assert_eq!(current("KEEPUSDT", "5m").await.volume, 0.5);
+    }
+
+    #[tokio::test]
+    async fn gap_closes_the_candles_without_close() {
+        setup("GAPUSDT").await;
+
+        proceed_data(tick("GAPUSDT", 0, 50.0, 51.0, 1.0), None).await;
+        proceed_data(tick("GAPUSDT", 3, 51.0, 52.0, 1.0), None).await;
+        // Ten minutes without tick
+        proceed_data(tick("GAPUSDT", 13, 60.0, 61.0, 2.0), None).await;
+
+        // Both minutes are followed by a gap, so they have no close
+        let history = HISTORY.lock().await.get("GAPUSDT", "1m", None);
+        assert_eq!(history.iter().map(|candle| candle.close).collect::<Vec<_>>(), vec![None, None]);
+
+        // The 5m bucket before the gap is closed without close either
+        let five = HISTORY.lock().await.get("GAPUSDT", "5m", None);
+        assert_eq!(five.len(), 1);
+        assert_eq!((five[0].open_time, five[0].close), (START, None));
+        assert_eq!(five[0].volume, 2.0);
+
+        // And saved with its last price
+        let saved = stored("GAPUSDT", "5m").await;
+        assert_eq!((saved[0].close, saved[0].partial), (52.0, false));
+
+        // The new candles start from the tick after the gap
+        assert_eq!(current("GAPUSDT", "5m").await.open_time, START + 10 * MINUTE);
+        assert_eq!(current("GAPUSDT", "5m").await.volume, 2.0);
+    }
+
+    // A REST API serving the 1m klines of the given minutes, one base volume each
+    async fn serve_klines(minutes: std::ops::Range<i64>) -> String {
+        use axum::{Json, Router, extract::Query, routing::get};
+
+        let klines = move |Query(query): Query<HashMap<String, String>>| async move {
+            let bound = |name: &str| query.get(name).and_then(|value| value.parse::<i64>().ok()).unwrap();
+            let (from, to) = (bound("startTime"), bound("endTime"));
+
+            Json(minutes.clone()
+                .map(|minute| START + minute * MINUTE)
+                .filter(|open_time| (from..=to).contains(open_time))
+                .map(|open_time| {
+                    let open = 100.0 + ((open_time - START) / MINUTE) as f64;
+                    serde_json::json!([open_time, open.to_string(), (open + 1.0).to_string(), (open - 0.5).to_string(), (open + 1.0).to_string(), "1", open_time + MINUTE - 1, "100", 1])
+                })
+                .collect::<Vec<_>>())
+        };
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, Router::new().route("/api/v3/klines", get(klines))).await.unwrap();
+        });
+
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    async fn restart_closes_the_buckets_of_the_downtime() {
+        setup("DOWNUSDT").await;
+
+        // Stopped at 00:49, the minute and the buckets in progress are saved as partial
+        for minute in 0..50 {
+            proceed_data(tick("DOWNUSDT", minute, 100.0 + minute as f64, 101.0 + minute as f64, 1.0), None).await;
+        }
+        for timerange in ["1m", "5m", "1h"] {
+            add_partial_candle(&current("DOWNUSDT", timerange).await, None).await;
+        }
+        write_pending().await;
+
+        // Restarted at 01:10:30, after the hour boundary
+        let rest_url = serve_klines(0..71).await;
+        let backfill = Backfill { provider: "Binance", rest_url: &rest_url };
+        rebuild_candles(vec!["DOWNUSDT".to_string()], false, Some(backfill), START + 70 * MINUTE + 30_000).await.unwrap();
+
+        // The hour of the shutdown is closed with every minute of it
+        let hours = stored("DOWNUSDT", "1h").await;
+        assert_eq!(hours.len(), 1);
+        assert_eq!((hours[0].open_time, hours[0].partial), (START, false));
+        assert_eq!((hours[0].open, hours[0].close, hours[0].volume), (100.0, 160.0, 60.0));
+
+        // So are the 5m buckets, the one partial at shutdown too
+        let fives = stored("DOWNUSDT", "5m").await;
+        assert_eq!(fives.iter().map(|candle| candle.open_time).collect::<Vec<_>>(), (0..14).map(|bucket| START + bucket * 5 * MINUTE).collect::<Vec<_>>());
+        assert!(fives.iter().all(|candle| !candle.partial && candle.volume == 5.0));
+
+        // The candles in progress go on from the backfilled minutes
+        let hour = current("DOWNUSDT", "1h").await;
+        assert_eq!((hour.open_time, hour.volume), (START + 60 * MINUTE, 11.0));
+        assert_eq!(current("DOWNUSDT", "5m").await.open_time, START + 70 * MINUTE);
+    }
+}
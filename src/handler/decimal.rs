@@ -0,0 +1,93 @@
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+// Key of the exact values in the candles of a symbol
+pub const EXACT_KEY: &str = "exact";
+
+// Exact (decimal) prices and volumes of a candle
+// Parsed from the exchange strings, so the tick size is preserved
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExactValues {
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Option<Decimal>,
+    pub price: Decimal,
+    pub volume: Decimal,
+    pub usdt_volume: Decimal,
+}
+
+// Exact values of every candle of a symbol
+// It follows the same steps as the f64 aggregation in proceed_data
+// But the volumes are summed without accumulating errors
+#[derive(Clone, Debug, Default)]
+pub struct ExactState {
+    pub candles: HashMap<String, ExactValues>,
+    // Live volume of the actual 1m candle
+    pub volume: Decimal,
+    pub usdt_volume: Decimal,
+    // What the last tick adds to each candle
+    volume_to_add: Decimal,
+    usdt_volume_to_add: Decimal,
+}
+
+impl ExactState {
+    // The first tick of a 1m candle
+    // The whole volume has to be added
+    pub fn start_volume(&mut self, new: &ExactValues) {
+        self.volume_to_add = new.volume;
+        self.usdt_volume_to_add = new.usdt_volume;
+        self.volume = Decimal::ZERO;
+        self.usdt_volume = Decimal::ZERO;
+    }
+
+    // Another tick of the same 1m candle
+    // Only the difference with the last tick has to be added
+    pub fn update_volume(&mut self, new: &ExactValues) {
+        self.volume_to_add = new.volume - self.volume;
+        self.usdt_volume_to_add = new.usdt_volume - self.usdt_volume;
+    }
+
+    // Once every timerange is updated
+    pub fn commit_volume(&mut self) {
+        self.volume += self.volume_to_add;
+        self.usdt_volume += self.usdt_volume_to_add;
+    }
+
+    // Start a new candle for the timerange
+    pub fn open(&mut self, timerange: &str, new: &ExactValues) {
+        self.candles.insert(timerange.to_string(), new.clone());
+    }
+
+    // Update the candle of the timerange with a tick in the same range
+    pub fn update(&mut self, timerange: &str, new: &ExactValues) {
+        if let Some(candle) = self.candles.get_mut(timerange) {
+            candle.low = candle.low.min(new.low);
+            candle.high = candle.high.max(new.high);
+            candle.price = new.price;
+            candle.volume += self.volume_to_add;
+            candle.usdt_volume += self.usdt_volume_to_add;
+        }
+    }
+
+    // Close the candle of the timerange and start the new one
+    // Returns the closed candle, if we had its exact values
+    pub fn close(&mut self, timerange: &str, new: &ExactValues, continuous: bool) -> Option<ExactValues> {
+        let closed = self.candles.remove(timerange).map(|mut closed| {
+            if continuous {
+                closed.close = Some(new.open);
+            }
+
+            closed
+        });
+
+        // The new candle starts with the volume of this tick
+        self.open(timerange, new);
+        if let Some(candle) = self.candles.get_mut(timerange) {
+            candle.volume = self.volume_to_add;
+            candle.usdt_volume = self.usdt_volume_to_add;
+        }
+
+        closed
+    }
+}
@@ -0,0 +1,82 @@
+use common::Candle;
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
+use tokio::sync::Mutex;
+
+// The last closed candles of each symbol and timerange, oldest first
+// Loaded at startup, then fed by the aggregator
+// So the consumers that need history (indicators, detectors) don't wait for it to build up
+#[derive(Default)]
+pub struct History {
+    // Candles kept per symbol and timerange, 0 keeps nothing
+    capacity: usize,
+    series: HashMap<String, HashMap<String, VecDeque<Candle>>>,
+}
+
+// Candles of a history page when no limit is given, and at most
+// For the history requests of the clients
+pub const DEFAULT_PAGE_SIZE: usize = 500;
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+pub static HISTORY: Lazy<Mutex<History>> = Lazy::new(|| Mutex::new(History::default()));
+
+impl History {
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // The oldest candles are dropped if the capacity is smaller
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+
+        for timeranges in self.series.values_mut() {
+            for candles in timeranges.values_mut() {
+                while candles.len() > capacity {
+                    candles.pop_front();
+                }
+            }
+        }
+    }
+
+    // Add a closed candle
+    // A candle already there (same open time) is replaced, e.g. closed again after a restart
+    pub fn push(&mut self, candle: Candle) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let candles = self.series
+            .entry(candle.symbol.clone())
+            .or_default()
+            .entry(candle.timerange.clone())
+            .or_insert_with(|| VecDeque::with_capacity(self.capacity));
+
+        match candles.back_mut() {
+            Some(last) if last.open_time == candle.open_time => *last = candle,
+            // Older than the last one, the aggregation only moves forward
+            Some(last) if last.open_time > candle.open_time => (),
+            _ => {
+                if candles.len() == self.capacity {
+                    candles.pop_front();
+                }
+                candles.push_back(candle);
+            }
+        }
+    }
+
+    // The last candles of a symbol and timerange, oldest first
+    // All of them if no limit is given
+    pub fn get(&self, symbol: &str, timerange: &str, limit: Option<usize>) -> Vec<Candle> {
+        let Some(candles) = self.series.get(symbol).and_then(|timeranges| timeranges.get(timerange)) else {
+            return Vec::new();
+        };
+
+        let skip = limit.map_or(0, |limit| candles.len().saturating_sub(limit));
+        candles.iter().skip(skip).cloned().collect()
+    }
+
+    // Number of candles of a symbol and timerange
+    pub fn len(&self, symbol: &str, timerange: &str) -> usize {
+        self.series.get(symbol).and_then(|timeranges| timeranges.get(timerange)).map_or(0, VecDeque::len)
+    }
+}
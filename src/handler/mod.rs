@@ -1 +1,4 @@
-pub mod candle;
\ No newline at end of file
+pub mod candle;
+pub mod decimal;
+pub mod history;
+pub mod validation;
\ No newline at end of file
@@ -0,0 +1,90 @@
+use crate::utils::config::ValidationConfig;
+
+use chrono::Utc;
+use common::Candle;
+use std::fmt;
+
+// The rules a candle must respect to be aggregated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rule {
+    // A price or volume is NaN or infinite
+    NotFinite,
+    // A price or volume is negative (or a price is 0)
+    Negative,
+    HighBelowLow,
+    OpenOutOfRange,
+    CloseOutOfRange,
+    // The open time is too far from the wall clock
+    ClockSkew,
+    // The price moved too much compared to the previous candle
+    PriceJump,
+}
+
+impl Rule {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rule::NotFinite => "not_finite",
+            Rule::Negative => "negative",
+            Rule::HighBelowLow => "high_below_low",
+            Rule::OpenOutOfRange => "open_out_of_range",
+            Rule::CloseOutOfRange => "close_out_of_range",
+            Rule::ClockSkew => "clock_skew",
+            Rule::PriceJump => "price_jump",
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+// Check an incoming candle
+// The previous price is the last price we received for the symbol (if any)
+pub fn validate_candle(candle: &Candle, previous_price: Option<f64>, config: &ValidationConfig) -> Result<(), Rule> {
+    let price = candle.price.unwrap_or(candle.open);
+    let prices = [candle.open, candle.high, candle.low, price];
+
+    if prices.iter().chain([&candle.volume, &candle.usdt_volume]).any(|v| !v.is_finite()) {
+        return Err(Rule::NotFinite);
+    }
+
+    if prices.iter().any(|p| *p <= 0.0) || candle.volume < 0.0 || candle.usdt_volume < 0.0 {
+        return Err(Rule::Negative);
+    }
+
+    if candle.high < candle.low {
+        return Err(Rule::HighBelowLow);
+    }
+
+    if candle.open < candle.low || candle.open > candle.high {
+        return Err(Rule::OpenOutOfRange);
+    }
+
+    // The close is the actual price while the candle is open
+    if price < candle.low || price > candle.high {
+        return Err(Rule::CloseOutOfRange);
+    }
+
+    // The candle can't start in the future
+    // Nor be older than its duration plus the allowed skew
+    if config.max_clock_skew_secs > 0 {
+        let max_skew_ms = config.max_clock_skew_secs as i64 * 1_000;
+        let now = Utc::now().timestamp_millis();
+        let duration = candle.close_time - candle.open_time;
+
+        if candle.open_time - now > max_skew_ms || now - candle.open_time > duration + max_skew_ms {
+            return Err(Rule::ClockSkew);
+        }
+    }
+
+    if let Some(previous) = previous_price {
+        let max_jump = config.max_price_jump_pct;
+        if max_jump > 0.0 && previous > 0.0 && ((price - previous) / previous).abs() * 100.0 > max_jump {
+            return Err(Rule::PriceJump);
+        }
+    }
+
+    Ok(())
+}
@@ -1,30 +1,307 @@
-use common::server::connect_db;
 use core::CONFIG;
-use core::utils::config;
-use core::server::{database, websocket};
+use core::utils::{cli::{Cli, Command, ConfigCommand}, config::{self, DatabaseConfig, StorageBackend}, export::{self, ExportOptions}, layers, logging, metrics::METRICS, reload};
+use core::server::{database, http, migrations, shutdown, supervisor, watchdog, websocket};
+use core::server::store::{CandleStore, memory::MemoryStore, postgres::{self, PostgresStore}, sqlite::SqliteStore};
+use core::handler::candle;
+use core::providers::{import::{self, ImportOptions}, recorder, replay::{self, ReplayOptions}};
+
+use clap::Parser;
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::io::AsyncWrite;
+use tracing::{error, info};
 
 
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+    let options = cli.layer_options();
+
+    // Print the effective config without running the service
+    if let Some(Command::Config { action: ConfigCommand::Print }) = cli.command {
+        match layers::build_layers(&options) {
+            Ok(layered) => print!("{}", layers::render(&layered)),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+
+        return;
+    }
+
     // Load the configuration
-    config::load_config();
+    // We can't do anything without a valid one
+    if let Err(e) = config::load_config(options) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
     
     let config = CONFIG.get().unwrap().lock().await.clone();
+    logging::init_logging(&config.logging);
+    config::apply_timeranges(&config).await;
+
+    // Run the recorded frames through the pipeline instead of the provider
+    if let Some(Command::Replay { file, speed, fast, output, persist }) = cli.command {
+        let options = ReplayOptions {
+            file,
+            speed: if fast { None } else { Some(speed) },
+            provider: config.stream.provider.clone(),
+            decimal: config.params.decimal,
+        };
+
+        std::process::exit(run_replay(options, output, persist, &config.database).await);
+    }
+
+    // Load historical archives instead of the live stream
+    if let Some(Command::Import { paths, symbol, batch_size }) = cli.command {
+        let options = ImportOptions {
+            paths,
+            symbol,
+            batch_size: batch_size.max(1),
+            decimal: config.params.decimal,
+        };
+
+        std::process::exit(run_import(options, &config.database).await);
+    }
+
+    // Bring the database schema up to date
+    if let Some(Command::Migrate { dry_run }) = cli.command {
+        // The SQLite schema is created when the file is opened
+        if config.database.backend != StorageBackend::Postgres {
+            eprintln!("Migrations only apply to the postgres backend");
+            std::process::exit(shutdown::EXIT_FAILURE);
+        }
+
+        let result = match postgres::create_pool(&config.database) {
+            Ok(pool) => migrations::migrate(&pool, &config.database, dry_run).await.map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        match result {
+            Ok(pending) if pending.is_empty() => println!("The database is up to date"),
+            Ok(pending) => {
+                for migration in pending {
+                    if dry_run {
+                        println!("-- {} {} (pending)\n{}", migration.version, migration.name, migration.sql);
+                    } else {
+                        println!("{} {} applied", migration.version, migration.name);
+                    }
+                }
+            },
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(shutdown::EXIT_FAILURE);
+            }
+        }
+
+        return;
+    }
+
+    // Write the saved candles to a file
+    if let Some(Command::Export { symbol, timerange, from, to, format, output }) = cli.command {
+        let options = ExportOptions { symbol, timerange, from, to, format, output, decimal: config.params.decimal };
+
+        if let Err(e) = open_store(&config.database, false).await {
+            eprintln!("{}", e);
+            std::process::exit(shutdown::EXIT_FAILURE);
+        }
+        match export::export(&options).await {
+            Ok(count) => info!(count, "Export done"),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(shutdown::EXIT_FAILURE);
+            }
+        }
+
+        return;
+    }
 
     // Connect to the database
-    connect_db().await;
-    database::load_last_candles(config.params.symbols.clone()).await;
+    if let Err(e) = open_store(&config.database, config.database.migrate).await {
+        error!(error = %e, "Unable to prepare the database");
+        std::process::exit(shutdown::EXIT_FAILURE);
+    }
+    // Rebuild the candles in progress, then load the history (with the backfilled candles)
+    let backfill = config.warmup.backfill.then(|| database::Backfill {
+        provider: &config.stream.provider,
+        rest_url: &config.warmup.rest_url,
+    });
+    let warmup = async {
+        database::load_last_candles(config.params.symbols.clone(), config.params.decimal, backfill).await?;
+        database::load_history(&config.params.symbols, config.warmup.history).await
+    };
+    if let Err(e) = warmup.await {
+        error!(error = %e, "Unable to load the saved candles");
+        std::process::exit(shutdown::EXIT_FAILURE);
+    }
+    METRICS.warmup_done.store(true, Ordering::Relaxed);
+
+    // Keep the raw provider frames, so they can be replayed later
+    if !config.recorder.path.is_empty() {
+        if let Err(e) = recorder::start_recording(config.recorder.path.as_ref()) {
+            error!(path = %config.recorder.path, error = %e, "Unable to start the recorder");
+        }
+    }
+
+    // Every subsystem is supervised
+    // So it is restarted if it panics or stops
+    let supervisor_config = config.supervisor;
 
     // Run our webscocket (to send the data to the users)
-    let intra_websocket = tokio::spawn(async move{
-        websocket::connect_to_intra_websocket().await;
+    websocket::configure(&config.websocket);
+    supervisor::supervise("intra_websocket", true, supervisor_config, || {
+        Box::pin(websocket::connect_to_intra_websocket())
     });
-    
+
     // Connect to the provider websocket
-    let get_data = tokio::spawn(async move {
-        websocket::connect_to_provider_websocket().await;
+    supervisor::supervise("provider", true, supervisor_config, || {
+        Box::pin(websocket::connect_to_provider_websocket())
+    });
+
+    // Reload the config when it changes
+    // Not critical, the service can run with the current config
+    supervisor::supervise("config_watcher", false, supervisor_config, || {
+        Box::pin(reload::watch_config())
     });
 
-    // Run the two tasks concurrently
-    let _ = tokio::join!(intra_websocket, get_data);
-}
\ No newline at end of file
+    // Detect the symbols that stopped receiving updates
+    if config.watchdog.enabled {
+        supervisor::supervise("watchdog", false, supervisor_config, || {
+            Box::pin(watchdog::watch_feeds())
+        });
+    }
+
+    // Write the closed candles to the database
+    supervisor::supervise("db_writer", false, supervisor_config, || {
+        Box::pin(database::write_candles())
+    });
+
+    // Serve the health, readiness and metrics endpoints
+    if config.http.enabled {
+        let address = config.http.address.clone();
+        supervisor::supervise("http", false, supervisor_config, move || {
+            Box::pin(http::serve_http(address.clone()))
+        });
+    }
+
+    // Run until we are asked to stop
+    // Or a critical subsystem can't be restarted anymore
+    let exit_code = tokio::select! {
+        _ = shutdown::wait_for_signal() => shutdown::EXIT_OK,
+        _ = supervisor::wait_for_fatal() => {
+            error!("A critical subsystem failed, shutting down");
+            shutdown::EXIT_FAILURE
+        }
+    };
+
+    let deadline = Duration::from_secs(config.shutdown.deadline_secs);
+    let exit_code = shutdown::graceful_shutdown(deadline, exit_code).await;
+    std::process::exit(exit_code);
+}
+
+// Replay a recording and write the closed candles
+// The configured store is only used when asked to, the candles are kept in memory otherwise
+async fn run_replay(options: ReplayOptions, output: Option<PathBuf>, persist: bool, database_config: &DatabaseConfig) -> i32 {
+    if persist {
+        if let Err(e) = open_store(database_config, database_config.migrate).await {
+            eprintln!("{}", e);
+            return shutdown::EXIT_FAILURE;
+        }
+    } else {
+        database::set_store(Arc::new(MemoryStore::default()));
+    }
+
+    // The recorded candles are old, the clock skew rule would reject them all
+    CONFIG.get().unwrap().lock().await.validation.max_clock_skew_secs = 0;
+
+    let mut output: Box<dyn AsyncWrite + Unpin> = match output {
+        Some(path) => match tokio::fs::File::create(&path).await {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                eprintln!("Unable to create {}: {}", path.display(), e);
+                return shutdown::EXIT_FAILURE;
+            }
+        },
+        None => Box::new(tokio::io::stdout()),
+    };
+
+    let mut closed = candle::tap_closed_candles().unwrap();
+    let writer = tokio::spawn(database::write_candles());
+
+    let result = replay::replay(&options, &mut closed, &mut output).await;
+
+    // The candles still queued
+    writer.abort();
+    let pending = database::write_pending().await;
+    if pending > 0 {
+        eprintln!("{} candles could not be saved", pending);
+        return shutdown::EXIT_FAILURE;
+    }
+
+    match result {
+        Ok(stats) => {
+            info!(frames = stats.frames, candles = stats.candles, parse_errors = stats.parse_errors, closed_candles = stats.closed_candles, "Replay done");
+            shutdown::EXIT_OK
+        }
+        Err(e) => {
+            eprintln!("Unable to replay {}: {}", options.file.display(), e);
+            shutdown::EXIT_FAILURE
+        }
+    }
+}
+
+// Import archives with the live aggregation
+// The closed candles are saved in bulk instead of one by one
+async fn run_import(options: ImportOptions, database_config: &DatabaseConfig) -> i32 {
+    if let Err(e) = open_store(database_config, database_config.migrate).await {
+        eprintln!("{}", e);
+        return shutdown::EXIT_FAILURE;
+    }
+    database::set_persistence(false);
+
+    // The live-only rules don't apply to the archives
+    // They are old, the clock skew rule would reject them all
+    // And a price jump between two rows is a real move, not a bad tick
+    {
+        let mut config = CONFIG.get().unwrap().lock().await;
+        config.validation.max_clock_skew_secs = 0;
+        config.validation.max_price_jump_pct = 0.0;
+    }
+
+    let mut closed = candle::tap_closed_candles().unwrap();
+
+    match import::import(&options, &mut closed).await {
+        Ok(stats) => {
+            info!(files = stats.files, rows = stats.rows, invalid_rows = stats.invalid_rows, rejected_rows = stats.rejected_rows, skipped_rows = stats.skipped_rows, candles = stats.candles, "Import done");
+            shutdown::EXIT_OK
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            shutdown::EXIT_FAILURE
+        }
+    }
+}
+
+// Open the configured store before writing to it
+// With Postgres, the pending migrations are applied first (unless disabled)
+async fn open_store(config: &DatabaseConfig, migrate: bool) -> Result<(), Box<dyn Error>> {
+    let store: Arc<dyn CandleStore> = match config.backend {
+        StorageBackend::Postgres => {
+            let pool = postgres::create_pool(config)?;
+            if migrate {
+                migrations::migrate(&pool, config, false).await?;
+            }
+
+            Arc::new(PostgresStore::new(pool, config.timescale))
+        },
+        StorageBackend::Sqlite => Arc::new(SqliteStore::open(&config.sqlite_path)?),
+        StorageBackend::Memory => Arc::new(MemoryStore::default()),
+    };
+
+    database::set_store(store);
+
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use crate::handler::decimal::ExactValues;
+use super::general::{parse_rest_klines, rest_klines_limit, rest_klines_url};
+use super::message::ParseError;
+
+use common::Candle;
+use std::fmt;
+use std::time::Duration;
+
+// Time given to each REST request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum BackfillError {
+    UnsupportedProvider(String),
+    Http(reqwest::Error),
+    Parse(ParseError),
+}
+
+impl fmt::Display for BackfillError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackfillError::UnsupportedProvider(provider) => write!(f, "Unable to backfill from {}", provider),
+            BackfillError::Http(e) => write!(f, "Unable to fetch the klines: {}", e),
+            BackfillError::Parse(e) => write!(f, "Unable to parse the klines: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BackfillError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BackfillError::UnsupportedProvider(_) => None,
+            BackfillError::Http(e) => Some(e),
+            BackfillError::Parse(e) => Some(e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for BackfillError {
+    fn from(e: reqwest::Error) -> Self {
+        BackfillError::Http(e)
+    }
+}
+
+impl From<ParseError> for BackfillError {
+    fn from(e: ParseError) -> Self {
+        BackfillError::Parse(e)
+    }
+}
+
+// Fetch the 1m candles of a symbol opened in [from, to] (ms) from the REST API of the provider
+// Oldest first, the last one may still be in progress
+pub async fn fetch_1m_candles(provider: &str, rest_url: &str, symbol: &str, from_ms: i64, to_ms: i64) -> Result<Vec<(Candle, ExactValues)>, BackfillError> {
+    let limit = rest_klines_limit(provider);
+    if limit == 0 {
+        return Err(BackfillError::UnsupportedProvider(provider.to_string()));
+    }
+
+    let client = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+
+    // One page per request, from the open time following the last kline
+    let mut candles = Vec::new();
+    let mut from_ms = from_ms;
+    while from_ms <= to_ms {
+        let url = rest_klines_url(provider, rest_url, symbol, from_ms, to_ms)
+            .ok_or_else(|| BackfillError::UnsupportedProvider(provider.to_string()))?;
+        let body = client.get(&url).send().await?.error_for_status()?.text().await?;
+
+        let page = parse_rest_klines(symbol, &body, provider)?;
+        let full = page.len() >= limit;
+        match page.last() {
+            Some((last, _)) => from_ms = last.open_time + 60_000,
+            None => break,
+        }
+
+        candles.extend(page);
+        if !full {
+            break;
+        }
+    }
+
+    Ok(candles)
+}
@@ -1,39 +1,243 @@
 use common::Candle;
-use serde_json::Value;
-
-pub fn parse_message(message: &str) -> Option<Candle> {
-    // Parse the JSON message from Binance
-    // The data we want is in the "k" field whichi is in the "data" field
-    let parsed: Value = serde_json::from_str(message).ok()?;
-    let data = &parsed["data"];
-    let kline = &data["k"];
-
-    // Extract the relevant fields from the kline object
-    // And convert them to the appropriate types
-    let open_time = kline["t"].as_i64()?;
-    let close_time = kline["T"].as_i64()?;
-    let symbol = kline["s"].as_str()?.to_string();
-    let timerange = kline["i"].as_str()?.to_string();
-    let open = kline["o"].as_str()?.parse::<f64>().ok()?;
-    let price = kline["c"].as_str()?.parse::<f64>().ok()?;
-    let high = kline["h"].as_str()?.parse::<f64>().ok()?;
-    let low = kline["l"].as_str()?.parse::<f64>().ok()?;
-    let volume = kline["v"].as_str()?.parse::<f64>().ok()?;
-    let usdt_volume = volume * price;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer};
+use std::borrow::Cow;
+
+use crate::handler::decimal::ExactValues;
+use super::message::{ParseError, ProviderMessage};
+
+// Typed view of a Binance frame
+// The strings are borrowed from the frame so nothing is allocated while parsing
+// Every field is optional since the same struct covers the stream data and the request answers
+#[derive(Deserialize)]
+struct Frame<'a> {
+    // Set on the answers to our requests (e.g. SUBSCRIBE)
+    id: Option<i64>,
+    #[serde(borrow)]
+    error: Option<ErrorBody<'a>>,
+    // Errors sent outside of a request
+    code: Option<i64>,
+    #[serde(borrow)]
+    msg: Option<Cow<'a, str>>,
+    // The data of a combined stream
+    #[serde(borrow)]
+    data: Option<StreamData<'a>>,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody<'a> {
+    code: Option<i64>,
+    #[serde(borrow)]
+    msg: Option<Cow<'a, str>>,
+}
+
+#[derive(Deserialize)]
+struct StreamData<'a> {
+    #[serde(rename = "e")]
+    event: &'a str,
+    #[serde(rename = "k", borrow)]
+    kline: Option<Kline<'a>>,
+}
+
+#[derive(Deserialize)]
+struct Kline<'a> {
+    #[serde(rename = "t")]
+    open_time: i64,
+    #[serde(rename = "T")]
+    close_time: i64,
+    #[serde(rename = "s")]
+    symbol: &'a str,
+    #[serde(rename = "i")]
+    timerange: &'a str,
+    // Binance sends the numbers as strings to keep their precision
+    #[serde(rename = "o", borrow, deserialize_with = "number_from_str")]
+    open: Number<'a>,
+    #[serde(rename = "c", borrow, deserialize_with = "number_from_str")]
+    close: Number<'a>,
+    #[serde(rename = "h", borrow, deserialize_with = "number_from_str")]
+    high: Number<'a>,
+    #[serde(rename = "l", borrow, deserialize_with = "number_from_str")]
+    low: Number<'a>,
+    #[serde(rename = "v", borrow, deserialize_with = "number_from_str")]
+    volume: Number<'a>,
+}
+
+// A number of a kline, with the exchange string it comes from
+// The string is parsed again as a decimal in decimal mode
+struct Number<'a> {
+    value: f64,
+    raw: &'a str,
+}
+
+impl Number<'_> {
+    fn exact(&self) -> Result<Decimal, ParseError> {
+        Decimal::from_str_exact(self.raw).map_err(|_| ParseError::InvalidNumber(self.raw.to_string()))
+    }
+}
+
+// Parse a number sent as a string (e.g. "0.01000000")
+// Without allocating the string
+fn number_from_str<'de: 'a, 'a, D>(deserializer: D) -> Result<Number<'a>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw = <&str>::deserialize(deserializer)?;
+    let value = raw.parse::<f64>().map_err(serde::de::Error::custom)?;
+
+    Ok(Number { value, raw })
+}
+
+// With `decimal`, the kline also gets its exact values, from the same parsing of the frame
+pub fn parse_message(message: &str, decimal: bool) -> Result<ProviderMessage, ParseError> {
+    let frame: Frame = serde_json::from_str(message).map_err(ParseError::InvalidJson)?;
+
+    // Answer to a request
+    // {"result":null,"id":1} or {"error":{"code":2,"msg":"..."},"id":1}
+    if let Some(error) = frame.error {
+        return Ok(ProviderMessage::Error {
+            code: error.code,
+            message: error.msg.unwrap_or_default().into_owned(),
+        });
+    }
+    if frame.id.is_some() {
+        return Ok(ProviderMessage::SubscriptionAck { id: frame.id });
+    }
+
+    // Error sent outside of a request
+    // {"code":1,"msg":"..."}
+    if let (Some(code), Some(msg)) = (frame.code, frame.msg) {
+        return Ok(ProviderMessage::Error { code: Some(code), message: msg.into_owned() });
+    }
+
+    // The data we want is in the "k" field of the "data" field
+    let data = match frame.data {
+        Some(data) => data,
+        None => return Ok(ProviderMessage::Unknown(String::new())),
+    };
+    let kline = match (data.event, data.kline) {
+        ("kline", Some(kline)) => kline,
+        ("kline", None) => return Err(ParseError::MissingKline),
+        (event, _) => return Ok(ProviderMessage::Unknown(event.to_string())),
+    };
 
     // Create a Candle object with the extracted data
-    // and return it
-    Some(Candle {
-        open_time,
-        close_time,
-        symbol,
-        timerange,
-        open,
+    let candle = Candle {
+        open_time: kline.open_time,
+        close_time: kline.close_time,
+        symbol: kline.symbol.to_string(),
+        timerange: kline.timerange.to_string(),
+        open: kline.open.value,
         close: None, // Because the close is the actual price
-        high,
-        low,
-        price: price.into(),
+        high: kline.high.value,
+        low: kline.low.value,
+        price: kline.close.value.into(),
+        volume: kline.volume.value,
+        usdt_volume: kline.volume.value * kline.close.value,
+    };
+
+    // The same strings parsed as decimals, so nothing is lost
+    let exact = if decimal {
+        let price = kline.close.exact()?;
+        let volume = kline.volume.exact()?;
+
+        Some(ExactValues {
+            open: kline.open.exact()?,
+            high: kline.high.exact()?,
+            low: kline.low.exact()?,
+            close: None, // Because the close is the actual price
+            price,
+            volume,
+            usdt_volume: volume * price,
+        })
+    } else {
+        None
+    };
+
+    Ok(ProviderMessage::Kline(candle, exact.map(Box::new)))
+}
+
+// Duration of a 1m kline in the archives (the close time is the last millisecond)
+const KLINE_1M_MS: i64 = 59_999;
+
+// Parse a line of the Binance public data kline archives (data.binance.vision)
+// open_time,open,high,low,close,volume,close_time,quote_volume,trades,taker_base,taker_quote,ignore
+// The times are in milliseconds, or in microseconds in the recent spot archives
+pub fn parse_archive_row(symbol: &str, row: &str) -> Result<(Candle, ExactValues), ParseError> {
+    let fields: Vec<&str> = row.trim().split(',').collect();
+    if fields.len() < 7 {
+        return Err(ParseError::InvalidRow(format!("expected at least 7 fields, got {}", fields.len())));
+    }
+
+    let time = |field: &str| -> Result<i64, ParseError> {
+        let time: i64 = field.parse().map_err(|_| ParseError::InvalidRow(format!("invalid time {:?}", field)))?;
+        // Microseconds since 2025 on the spot archives
+        Ok(if time >= 1_000_000_000_000_000 { time / 1_000 } else { time })
+    };
+    let number = |field: &str| -> Result<Decimal, ParseError> {
+        Decimal::from_str_exact(field).map_err(|_| ParseError::InvalidRow(format!("invalid number {:?}", field)))
+    };
+
+    let open_time = time(fields[0])?;
+    let close_time = time(fields[6])?;
+    if close_time - open_time != KLINE_1M_MS {
+        return Err(ParseError::InvalidRow("not a 1m kline".to_string()));
+    }
+
+    let price = number(fields[4])?;
+    let volume = number(fields[5])?;
+    let exact = ExactValues {
+        open: number(fields[1])?,
+        high: number(fields[2])?,
+        low: number(fields[3])?,
+        close: None, // Because the close is the actual price
+        price,
         volume,
-        usdt_volume
-    })
-}
\ No newline at end of file
+        usdt_volume: volume * price,
+    };
+
+    // Same candle as a live kline with this close price
+    let float = |value: Decimal| f64::try_from(value).unwrap_or(f64::NAN);
+    let candle = Candle {
+        open_time,
+        close_time,
+        symbol: symbol.to_string(),
+        timerange: "1m".to_string(),
+        open: float(exact.open),
+        close: None,
+        high: float(exact.high),
+        low: float(exact.low),
+        price: Some(float(price)),
+        volume: float(volume),
+        usdt_volume: float(volume) * float(price),
+    };
+
+    Ok((candle, exact))
+}
+
+// Klines of the REST API, at most 1000 per request
+// GET <rest_url>/api/v3/klines?symbol=BTCUSDT&interval=1m&startTime=...&endTime=...
+pub const REST_KLINES_LIMIT: usize = 1000;
+
+pub fn rest_klines_url(rest_url: &str, symbol: &str, from_ms: i64, to_ms: i64) -> String {
+    format!("{}/api/v3/klines?symbol={}&interval=1m&startTime={}&endTime={}&limit={}", rest_url.trim_end_matches('/'), symbol.to_uppercase(), from_ms, to_ms, REST_KLINES_LIMIT)
+}
+
+// The REST klines have the columns of the archives, as a JSON array per kline
+// [open_time, "open", "high", "low", "close", "volume", close_time, "quote_volume", trades, ...]
+pub fn parse_rest_klines(symbol: &str, body: &str) -> Result<Vec<(Candle, ExactValues)>, ParseError> {
+    let klines: Vec<Vec<serde_json::Value>> = serde_json::from_str(body).map_err(ParseError::InvalidJson)?;
+
+    klines.iter()
+        .map(|kline| {
+            let row = kline.iter()
+                .map(|field| match field {
+                    serde_json::Value::String(value) => value.clone(),
+                    other => other.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(",");
+
+            parse_archive_row(symbol, &row)
+        })
+        .collect()
+}
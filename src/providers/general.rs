@@ -1,38 +1,79 @@
-use common::Candle;
+use tracing::error;
 
 use super::binance;
+use super::message::{ParseError, ProviderMessage};
+use crate::handler::decimal::ExactValues;
+use common::Candle;
+
+// Providers we know how to connect to
+pub const SUPPORTED_PROVIDERS: &[&str] = &["Binance"];
+
+// Stream types we know how to parse for each provider
+// The aggregator is built on top of 1m candles
+pub fn supported_stream_types(provider: &str) -> &'static [&'static str] {
+    match provider {
+        "Binance" => &["kline_1m"],
+        _ => &[],
+    }
+}
 
 // This function constructs a WebSocket URL for a given provider and symbols.
 // Each provider has its own URL format
 // and the function handles the construction based on the provider's requirements.
-pub fn build_stream_url(provider: &str, symbols: &[String], stream_type: &str) -> String {
+// The base url comes from the config, so we can point to a testnet or a local mock
+pub fn build_stream_url(provider: &str, url: &str, symbols: &[String], stream_type: &str) -> String {
     match provider {
         "Binance" => {
             // Construct the WebSocket URL for Binance
-            // The URL format is wss://stream.binance.com:9443/stream?streams=<symbol1>@<stream_type>/<symbol2>@<stream_type>...
+            // The URL format is <url>?streams=<symbol1>@<stream_type>/<symbol2>@<stream_type>...
+            // With <url> being the combined stream endpoint (e.g. wss://stream.binance.com:9443/stream)
             let symbol_list = symbols.iter()
                 .map(|symbol| format!("{}@{}", symbol.to_lowercase(), stream_type))
                 .collect::<Vec<_>>()
                 .join("/");
 
-            format!("wss://stream.binance.com:9443/stream?streams={}", symbol_list)
+            format!("{}?streams={}", url.trim_end_matches('/'), symbol_list)
         },
         _ => {
-            eprintln!("Unsupported provider: {}", provider);
+            error!(provider, "Unsupported provider");
             String::new()
         }
     }
 }
 
-// This function returns a parsed Candle object from the message received from the WebSocket stream
+// This function parses the message received from the WebSocket stream
+// It is either a candle, or a message about the stream (ack, error, ...)
 // Each provider has its own message format
 // and the function handles the parsing based on the provider's requirements.
-pub fn parse_candle(message: &str, provider: &str) -> Option<Candle> {
+// In decimal mode, the candles also carry their exact values
+pub fn parse_candle(message: &str, provider: &str, decimal: bool) -> Result<ProviderMessage, ParseError> {
     match provider {
-        "Binance" => binance::parse_message(message),
-        _ => {
-            eprintln!("Unsupported provider: {}", provider);
-            None
-        }
+        "Binance" => binance::parse_message(message, decimal),
+        _ => Err(ParseError::UnsupportedProvider(provider.to_string())),
+    }
+}
+
+// URL of the 1m klines of a symbol over [from, to] (ms), on the REST API of the provider
+// None if we can't backfill from this provider
+pub fn rest_klines_url(provider: &str, rest_url: &str, symbol: &str, from_ms: i64, to_ms: i64) -> Option<String> {
+    match provider {
+        "Binance" => Some(binance::rest_klines_url(rest_url, symbol, from_ms, to_ms)),
+        _ => None,
+    }
+}
+
+// Parse the answer of the klines REST API
+pub fn parse_rest_klines(symbol: &str, body: &str, provider: &str) -> Result<Vec<(Candle, ExactValues)>, ParseError> {
+    match provider {
+        "Binance" => binance::parse_rest_klines(symbol, body),
+        _ => Err(ParseError::UnsupportedProvider(provider.to_string())),
     }
-}
\ No newline at end of file
+}
+
+// Maximum number of klines per REST request
+pub fn rest_klines_limit(provider: &str) -> usize {
+    match provider {
+        "Binance" => binance::REST_KLINES_LIMIT,
+        _ => 0,
+    }
+}
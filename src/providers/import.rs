@@ -0,0 +1,278 @@
+use crate::handler::candle::{CANDLES, CandleOrValue, proceed_data, register_symbols};
+use crate::handler::decimal::{EXACT_KEY, ExactValues};
+use crate::handler::validation::validate_candle;
+use crate::providers::binance::parse_archive_row;
+use crate::server::database::{add_candles, refresh_derived};
+use crate::server::store::{StoreError, StoredCandle};
+use crate::CONFIG;
+
+use common::{Candle, TIMERANGES};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tracing::{info, warn};
+
+pub struct ImportOptions {
+    // Archive files, or directories containing them
+    pub paths: Vec<PathBuf>,
+    // Symbol of every file (otherwise taken from the file names, e.g. BTCUSDT-1m-2024-01-01.zip)
+    pub symbol: Option<String>,
+    // Candles written at once
+    pub batch_size: usize,
+    pub decimal: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportStats {
+    pub files: u64,
+    pub rows: u64,
+    pub invalid_rows: u64,
+    // Rows that failed the validation
+    pub rejected_rows: u64,
+    // Rows older than (or the same as) the previous one of the symbol
+    pub skipped_rows: u64,
+    pub candles: u64,
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Read { path: PathBuf, source: std::io::Error },
+    Archive { path: PathBuf, source: zip::result::ZipError },
+    UnknownSymbol(PathBuf),
+    Database(StoreError),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Read { path, source } => write!(f, "Unable to read {}: {}", path.display(), source),
+            ImportError::Archive { path, source } => write!(f, "Unable to open archive {}: {}", path.display(), source),
+            ImportError::UnknownSymbol(path) => write!(f, "Unable to get the symbol of {} (use --symbol)", path.display()),
+            ImportError::Database(e) => write!(f, "Unable to write the candles: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ImportError::Read { source, .. } => Some(source),
+            ImportError::Archive { source, .. } => Some(source),
+            ImportError::Database(e) => Some(e),
+            ImportError::UnknownSymbol(_) => None,
+        }
+    }
+}
+
+impl From<StoreError> for ImportError {
+    fn from(e: StoreError) -> Self {
+        ImportError::Database(e)
+    }
+}
+
+// Import Binance kline archives (data.binance.vision) into the candles table
+// The 1m rows go through the live aggregation, and the closed candles are copied in bulk
+pub async fn import(options: &ImportOptions, closed: &mut UnboundedReceiver<(Candle, Option<ExactValues>)>) -> Result<ImportStats, ImportError> {
+    let files = collect_files(options)?;
+    let timeranges = TIMERANGES.lock().await.clone();
+    // Without the live-only rules (clock skew and price jump), see run_import
+    let validation = CONFIG.get().unwrap().lock().await.validation.clone();
+
+    let mut stats = ImportStats::default();
+    let mut batch = Vec::with_capacity(options.batch_size);
+    // Open times of the imported rows, of every symbol
+    let mut imported: Option<(i64, i64)> = None;
+
+    for (symbol, paths) in files {
+        register_symbols(std::slice::from_ref(&symbol), &timeranges).await;
+        let mut first_open_time = None;
+        let mut last_open_time = None;
+
+        for path in paths {
+            // The archives are read (and unzipped) outside of the runtime
+            let reader = {
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || read_archive(&path))
+            };
+            let content = reader.await
+                .unwrap_or_else(|e| Err(ImportError::Read { path: path.clone(), source: std::io::Error::other(e) }))?;
+            stats.files += 1;
+
+            let mut rows = 0;
+            for (index, line) in content.lines().enumerate() {
+                // Some archives start with a header
+                if line.trim().is_empty() || (index == 0 && line.starts_with(|c: char| c.is_ascii_alphabetic())) {
+                    continue;
+                }
+
+                let (candle, exact) = match parse_archive_row(&symbol, line) {
+                    Ok(row) => row,
+                    Err(e) => {
+                        stats.invalid_rows += 1;
+                        warn!(file = %path.display(), line = index + 1, error = %e, "Invalid archive row");
+                        continue;
+                    }
+                };
+
+                // Checked here so the rejected rows are counted
+                // The live aggregation would only log them
+                if validation.enabled {
+                    if let Err(rule) = validate_candle(&candle, None, &validation) {
+                        stats.rejected_rows += 1;
+                        warn!(file = %path.display(), line = index + 1, %rule, "Archive row rejected");
+                        continue;
+                    }
+                }
+
+                // The aggregation expects the candles in order
+                if last_open_time.is_some_and(|last| candle.open_time <= last) {
+                    stats.skipped_rows += 1;
+                    continue;
+                }
+                first_open_time.get_or_insert(candle.open_time);
+                last_open_time = Some(candle.open_time);
+                rows += 1;
+
+                proceed_data(candle, options.decimal.then_some(exact)).await;
+
+                while let Ok((candle, exact)) = closed.try_recv() {
+                    batch.push(StoredCandle::new(&candle, exact.as_ref(), false));
+                }
+                if batch.len() >= options.batch_size {
+                    stats.candles += add_candles(&batch).await?;
+                    batch.clear();
+                }
+            }
+
+            stats.rows += rows;
+            info!(%symbol, file = %path.display(), rows, "Archive imported");
+        }
+
+        // The candles still open after the last row
+        if let Some(last_open_time) = last_open_time {
+            batch.extend(take_open_candles(&symbol, last_open_time).await);
+        }
+        if let (Some(first), Some(last)) = (first_open_time, last_open_time) {
+            imported = Some(imported.map_or((first, last), |(from, to)| (from.min(first), to.max(last))));
+        }
+        if !batch.is_empty() {
+            stats.candles += add_candles(&batch).await?;
+            batch.clear();
+        }
+    }
+
+    // In TimescaleDB mode, the imported range is older than what the policies refresh
+    // Widened to whole days, the largest bucket
+    if let Some((from, to)) = imported {
+        const DAY_MS: i64 = 24 * 60 * 60_000;
+        refresh_derived(from - from.rem_euclid(DAY_MS), to - to.rem_euclid(DAY_MS) + DAY_MS).await?;
+    }
+
+    Ok(stats)
+}
+
+// Remove the candles of the symbol that were not closed by a following row
+// They are complete if the last row is their last minute, partial otherwise
+async fn take_open_candles(symbol: &str, last_open_time: i64) -> Vec<StoredCandle> {
+    let mut entries = match CANDLES.lock().await.remove(symbol) {
+        Some(entries) => entries,
+        None => return Vec::new(),
+    };
+
+    let mut exact_candles = match entries.remove(EXACT_KEY) {
+        Some(CandleOrValue::Exact(state)) => state.candles,
+        _ => Default::default(),
+    };
+
+    entries.into_iter()
+        .filter_map(|(timerange, entry)| match entry {
+            CandleOrValue::Candle(candle) if candle.open_time != 0 => {
+                let exact = exact_candles.remove(&timerange);
+                let partial = last_open_time + 60_000 < candle.close_time + 1_000;
+
+                Some(StoredCandle::new(&candle, exact.as_ref(), partial))
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+// Find the archives to import, grouped by symbol
+// Sorted by name, so the days (or months) are in order
+fn collect_files(options: &ImportOptions) -> Result<BTreeMap<String, Vec<PathBuf>>, ImportError> {
+    let mut paths = Vec::new();
+    for path in &options.paths {
+        find_archives(path, &mut paths)?;
+    }
+
+    let mut files: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for path in paths {
+        let symbol = match &options.symbol {
+            Some(symbol) => symbol.clone(),
+            None => path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.split('-').next())
+                .filter(|symbol| !symbol.is_empty() && symbol.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()))
+                .ok_or_else(|| ImportError::UnknownSymbol(path.clone()))?
+                .to_string(),
+        };
+
+        files.entry(symbol).or_default().push(path);
+    }
+
+    for paths in files.values_mut() {
+        paths.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    }
+
+    Ok(files)
+}
+
+fn find_archives(path: &Path, archives: &mut Vec<PathBuf>) -> Result<(), ImportError> {
+    let read_error = |source| ImportError::Read { path: path.to_path_buf(), source };
+
+    if !path.is_dir() {
+        archives.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    for entry in std::fs::read_dir(path).map_err(read_error)? {
+        let entry_path = entry.map_err(read_error)?.path();
+        let is_archive = entry_path.extension().is_some_and(|ext| ext == "csv" || ext == "zip");
+
+        if entry_path.is_dir() || is_archive {
+            find_archives(&entry_path, archives)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Read the rows of a CSV file, or of the CSV files in a ZIP archive
+fn read_archive(path: &Path) -> Result<String, ImportError> {
+    let read_error = |source| ImportError::Read { path: path.to_path_buf(), source };
+    let archive_error = |source| ImportError::Archive { path: path.to_path_buf(), source };
+
+    let mut file = File::open(path).map_err(read_error)?;
+    let mut content = String::new();
+
+    if path.extension().is_some_and(|ext| ext == "zip") {
+        let mut archive = zip::ZipArchive::new(file).map_err(archive_error)?;
+
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index).map_err(archive_error)?;
+            if entry.name().ends_with(".csv") {
+                entry.read_to_string(&mut content).map_err(read_error)?;
+                if !content.ends_with('\n') {
+                    content.push('\n');
+                }
+            }
+        }
+    } else {
+        file.read_to_string(&mut content).map_err(read_error)?;
+    }
+
+    Ok(content)
+}
@@ -0,0 +1,53 @@
+use common::Candle;
+
+use crate::handler::decimal::ExactValues;
+use std::fmt;
+
+// Every kind of message a provider can send us
+#[derive(Clone, Debug)]
+pub enum ProviderMessage {
+    // A candle update, with its exact values in decimal mode
+    // Boxed, they are much larger than the other variants
+    Kline(Candle, Option<Box<ExactValues>>),
+    // Acknowledgement of a subscription request
+    SubscriptionAck { id: Option<i64> },
+    // The provider reports an error
+    Error { code: Option<i64>, message: String },
+    // A valid message we don't handle (the event type if we know it)
+    Unknown(String),
+}
+
+// A message that can't be understood
+#[derive(Debug)]
+pub enum ParseError {
+    // Not JSON, or a field is missing or has the wrong type
+    InvalidJson(serde_json::Error),
+    // A kline event without its kline
+    MissingKline,
+    // A price or volume that is not a number
+    InvalidNumber(String),
+    UnsupportedProvider(String),
+    // A line of an archive file that is not a kline
+    InvalidRow(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::InvalidJson(e) => write!(f, "invalid message: {}", e),
+            ParseError::MissingKline => write!(f, "kline event without kline"),
+            ParseError::InvalidNumber(value) => write!(f, "invalid number {:?}", value),
+            ParseError::UnsupportedProvider(provider) => write!(f, "unsupported provider {}", provider),
+            ParseError::InvalidRow(reason) => write!(f, "invalid row: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::InvalidJson(e) => Some(e),
+            _ => None,
+        }
+    }
+}
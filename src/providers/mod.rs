@@ -1,2 +1,7 @@
+pub mod backfill;
 pub mod binance;
-pub mod general;
\ No newline at end of file
+pub mod general;
+pub mod import;
+pub mod message;
+pub mod recorder;
+pub mod replay;
\ No newline at end of file
@@ -0,0 +1,56 @@
+use chrono::Utc;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::error;
+
+// A raw provider frame and when we received it
+// One per line in the recording files
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RecordedFrame {
+    pub received_at: i64,
+    pub frame: String,
+}
+
+// The recording file, if the recorder is enabled
+static RECORDER: OnceCell<Mutex<BufWriter<File>>> = OnceCell::new();
+
+// Start recording the provider frames in the given file
+// The frames are appended, so a restart doesn't erase the previous ones
+pub fn start_recording(path: &Path) -> std::io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let _ = RECORDER.set(Mutex::new(BufWriter::new(file)));
+
+    Ok(())
+}
+
+// Record a frame (if the recorder is enabled)
+pub fn record(frame: &str) {
+    let recorder = match RECORDER.get() {
+        Some(recorder) => recorder,
+        None => return,
+    };
+
+    let line = RecordedFrame {
+        received_at: Utc::now().timestamp_millis(),
+        frame: frame.to_string(),
+    };
+
+    let mut writer = recorder.lock().unwrap();
+    let result = serde_json::to_writer(&mut *writer, &line).map_err(std::io::Error::from)
+        .and_then(|_| writer.write_all(b"\n"));
+
+    if let Err(e) = result {
+        error!(error = %e, "Unable to record the provider frame");
+    }
+}
+
+// Write the buffered frames to the file
+pub fn flush() {
+    if let Some(recorder) = RECORDER.get() {
+        let _ = recorder.lock().unwrap().flush();
+    }
+}
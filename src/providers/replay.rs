@@ -0,0 +1,105 @@
+use crate::handler::candle::{CANDLES, proceed_data, register_symbols};
+use crate::providers::general::parse_candle;
+use crate::providers::message::ProviderMessage;
+use crate::providers::recorder::RecordedFrame;
+
+use common::{Candle, TIMERANGES};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc::UnboundedReceiver;
+use crate::handler::decimal::ExactValues;
+use tokio::time::Instant;
+use tracing::warn;
+
+pub struct ReplayOptions {
+    // File written by the recorder
+    pub file: PathBuf,
+    // Speed factor compared to the recording (None to go as fast as possible)
+    pub speed: Option<f64>,
+    pub provider: String,
+    pub decimal: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct ReplayStats {
+    pub frames: u64,
+    pub candles: u64,
+    pub parse_errors: u64,
+    pub closed_candles: u64,
+}
+
+// Feed the recorded frames through the pipeline
+// Every closed candle is written as a JSON line to the output
+pub async fn replay<W>(options: &ReplayOptions, closed: &mut UnboundedReceiver<(Candle, Option<ExactValues>)>, output: &mut W) -> std::io::Result<ReplayStats>
+where
+    W: AsyncWrite + Unpin,
+{
+    let file = File::open(&options.file).await?;
+    let mut lines = BufReader::new(file).lines();
+
+    let mut stats = ReplayStats::default();
+    let started = Instant::now();
+    let mut first_received_at = None;
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let recorded: RecordedFrame = match serde_json::from_str(&line) {
+            Ok(recorded) => recorded,
+            Err(e) => {
+                warn!(error = %e, "Invalid recorded frame");
+                continue;
+            }
+        };
+        stats.frames += 1;
+
+        // Wait until the frame is due
+        // Relative to the first frame of the recording
+        if let Some(speed) = options.speed {
+            let first = *first_received_at.get_or_insert(recorded.received_at);
+            let offset = Duration::from_millis((recorded.received_at - first).max(0) as u64).div_f64(speed);
+            tokio::time::sleep_until(started + offset).await;
+        }
+
+        match parse_candle(&recorded.frame, &options.provider, options.decimal) {
+            Ok(ProviderMessage::Kline(candle, exact)) => {
+                stats.candles += 1;
+                register_symbol(&candle.symbol).await;
+
+                proceed_data(candle, exact.map(|exact| *exact)).await;
+            },
+            Ok(_) => (),
+            Err(e) => {
+                stats.parse_errors += 1;
+                warn!(error = %e, "Unable to parse recorded frame");
+            }
+        }
+
+        // Write the candles closed by this frame
+        while let Ok((candle, _)) = closed.try_recv() {
+            stats.closed_candles += 1;
+
+            let mut line = serde_json::to_vec(&candle)?;
+            line.push(b'\n');
+            output.write_all(&line).await?;
+        }
+    }
+
+    output.flush().await?;
+
+    Ok(stats)
+}
+
+// The recording may contain symbols that are not in the config
+async fn register_symbol(symbol: &str) {
+    if CANDLES.lock().await.contains_key(symbol) {
+        return;
+    }
+
+    let timeranges = TIMERANGES.lock().await.clone();
+    register_symbols(&[symbol.to_string()], &timeranges).await;
+}
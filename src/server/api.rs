@@ -0,0 +1,154 @@
+use crate::CONFIG;
+use crate::handler::candle::{CANDLES, CandleOrValue};
+use crate::handler::history::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+use crate::server::{database, watchdog};
+use crate::server::http::DB_CHECK_TIMEOUT;
+use crate::server::store::StoreError;
+use crate::utils::metrics::METRICS;
+
+use axum::{Json, Router, extract::{Path, Query}, http::StatusCode, response::{IntoResponse, Response}, routing::get};
+use chrono::{DateTime, Utc};
+use common::{Candle, TIMERANGES};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::atomic::Ordering;
+use tracing::warn;
+
+// Read-only access to the candles, for the services that don't keep a websocket open
+// The candles have the same JSON as the ones sent over the websocket
+pub fn router() -> Router {
+    Router::new()
+        .route("/symbols", get(symbols))
+        .route("/candles/{symbol}/{timerange}", get(candles))
+        .route("/candles/{symbol}/{timerange}/current", get(current_candle))
+        .route("/status", get(status))
+}
+
+// The open times are in ms
+// The candles opened in [from, to) from `from` if given, the last ones before `to` otherwise
+#[derive(Deserialize)]
+struct CandlesQuery {
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: Option<usize>,
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+// Only the configured symbols and timeranges are served
+async fn check_series(symbol: &str, timerange: &str) -> Result<(), Response> {
+    if !CONFIG.get().unwrap().lock().await.params.symbols.iter().any(|s| s == symbol) {
+        return Err(error(StatusCode::NOT_FOUND, &format!("Unknown symbol {}", symbol)));
+    }
+    if !TIMERANGES.lock().await.iter().any(|t| t == timerange) {
+        return Err(error(StatusCode::NOT_FOUND, &format!("Unknown timerange {}", timerange)));
+    }
+
+    Ok(())
+}
+
+async fn symbols() -> impl IntoResponse {
+    let symbols = CONFIG.get().unwrap().lock().await.params.symbols.clone();
+    let timeranges = TIMERANGES.lock().await.clone();
+
+    Json(json!({
+        "symbols": symbols,
+        "timeranges": timeranges,
+    }))
+}
+
+// The closed candles of a symbol and timerange, oldest first
+async fn candles(Path((symbol, timerange)): Path<(String, String)>, Query(query): Query<CandlesQuery>) -> Response {
+    if let Err(response) = check_series(&symbol, &timerange).await {
+        return response;
+    }
+
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let candles = match query.from {
+        Some(from) => {
+            let Some(from) = DateTime::<Utc>::from_timestamp_millis(from) else {
+                return error(StatusCode::BAD_REQUEST, "Invalid from");
+            };
+            let to = match query.to.map(DateTime::<Utc>::from_timestamp_millis) {
+                Some(None) => return error(StatusCode::BAD_REQUEST, "Invalid to"),
+                to => to.flatten(),
+            };
+            first_candles(&symbol, &timerange, from, to, limit).await
+        },
+        None => database::history_page(&symbol, &timerange, query.to, limit).await,
+    };
+
+    match candles {
+        Ok(candles) => Json(candles).into_response(),
+        Err(e) => {
+            warn!(symbol, timerange, error = %e, "Unable to load the candles");
+            error(StatusCode::INTERNAL_SERVER_ERROR, "Unable to load the candles")
+        }
+    }
+}
+
+// The first closed candles opened in [from, to)
+async fn first_candles(symbol: &str, timerange: &str, from: DateTime<Utc>, to: Option<DateTime<Utc>>, limit: usize) -> Result<Vec<Candle>, StoreError> {
+    let mut stream = database::stream_candles(symbol, timerange, Some(from), to).await?;
+
+    let mut candles = Vec::with_capacity(limit);
+    while let Some(candle) = stream.next().await {
+        let candle = candle?;
+        if candle.partial {
+            continue;
+        }
+
+        candles.push(candle.to_candle());
+        if candles.len() == limit {
+            break;
+        }
+    }
+
+    Ok(candles)
+}
+
+// The candle in progress of a symbol and timerange
+async fn current_candle(Path((symbol, timerange)): Path<(String, String)>) -> Response {
+    if let Err(response) = check_series(&symbol, &timerange).await {
+        return response;
+    }
+
+    let candles = CANDLES.lock().await;
+    match candles.get(&symbol).and_then(|entries| entries.get(&timerange)) {
+        // An empty candle until the first tick of the bucket
+        Some(CandleOrValue::Candle(candle)) if candle.open_time != 0 => Json(candle.clone()).into_response(),
+        _ => error(StatusCode::NOT_FOUND, "No candle in progress"),
+    }
+}
+
+// The state of the service and of the feed of each symbol
+async fn status() -> impl IntoResponse {
+    let config = CONFIG.get().unwrap().lock().await.clone();
+    let database_reachable = tokio::time::timeout(DB_CHECK_TIMEOUT, database::ping())
+        .await
+        .unwrap_or(false);
+
+    let provider_connected = METRICS.provider_connected.get() == 1;
+    let connected_at = provider_connected.then(|| METRICS.provider_connected_at.load(Ordering::Relaxed));
+
+    let symbols: Vec<_> = config.params.symbols.iter()
+        .map(|symbol| json!({
+            "symbol": symbol,
+            "last_tick": METRICS.last_tick(symbol),
+            "stale": watchdog::is_stale(symbol),
+        }))
+        .collect();
+
+    Json(json!({
+        "provider": config.stream.provider,
+        "provider_connected": provider_connected,
+        "connected_at": connected_at,
+        "warmup_done": METRICS.warmup_done.load(Ordering::Relaxed),
+        "database_reachable": database_reachable,
+        "clients": METRICS.intra_clients.get(),
+        "symbols": symbols,
+    }))
+}
@@ -1,99 +1,497 @@
-use common::DB_CLIENT;
-use crate::handler::candle::{CANDLES, CandleOrValue};
+use crate::handler::candle::{CANDLES, CandleOrValue, empty_entries, get_timerange};
+use crate::handler::decimal::{EXACT_KEY, ExactState, ExactValues};
+use crate::handler::history::HISTORY;
+use crate::handler::validation::Rule;
+use crate::providers::backfill::fetch_1m_candles;
+use crate::server::shutdown;
+use crate::server::store::{CandleStore, CandleStream, StoreError, StoredCandle};
+use crate::utils::metrics::METRICS;
 
 use chrono::{DateTime, Utc};
 use common::{Candle, TIMERANGES};
-use tokio::sync::Mutex;
-use std::collections::HashMap;
+use once_cell::sync::{Lazy, OnceCell};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::{debug, error, info, instrument, warn};
 
-// Facilitate access to the database client
-pub async fn get_db_client() -> Arc<Mutex<tokio_postgres::Client>> {
-    DB_CLIENT.get().expect("Database client not initialized").clone()
+// Where the candles are saved (Postgres, SQLite or memory)
+// Set once at startup, before anything is written
+static STORE: OnceCell<Arc<dyn CandleStore>> = OnceCell::new();
+
+pub fn set_store(store: Arc<dyn CandleStore>) {
+    if STORE.set(store).is_err() {
+        panic!("Candle store already initialized");
+    }
+}
+
+// Facilitate access to the store
+pub fn store() -> Arc<dyn CandleStore> {
+    STORE.get().expect("Candle store not initialized").clone()
+}
+
+// Set to false to skip the candle writes (e.g. import, which saves them in bulk)
+static PERSISTENCE: AtomicBool = AtomicBool::new(true);
+
+pub fn set_persistence(enabled: bool) {
+    PERSISTENCE.store(enabled, Ordering::Relaxed);
+}
+
+// Where the warmup fetches the 1m candles missing from the store
+pub struct Backfill<'a> {
+    pub provider: &'a str,
+    pub rest_url: &'a str,
 }
 
-pub async fn load_last_candles(symbols: Vec<String>) {
-    let client = get_db_client().await;
-    let client = client.lock().await;
+// The buckets closed during a downtime are saved if they started at most this long ago
+const MAX_CATCH_UP_MS: i64 = 7 * 24 * 60 * 60_000;
 
+// Rebuild the candle in progress of every timerange from the 1m candles of its bucket
+// So a restart at 13:47 gives the 12:00-16:00 4h candle, not the last row saved
+// The 1m candles missing since the last saved one are fetched from the provider (if given), and saved
+// With them, the buckets closed while the service was stopped are saved as closed
+// In decimal mode, the exact values of the candles are restored too
+pub async fn load_last_candles(symbols: Vec<String>, decimal: bool, backfill: Option<Backfill<'_>>) -> Result<(), StoreError> {
+    rebuild_candles(symbols, decimal, backfill, Utc::now().timestamp_millis()).await
+}
+
+// Same, at a given time (ms)
+pub async fn rebuild_candles(symbols: Vec<String>, decimal: bool, backfill: Option<Backfill<'_>>, now: i64) -> Result<(), StoreError> {
     // Load all timeranges
     let timeranges = {
         let timeranges = TIMERANGES.lock().await;
         timeranges.clone() 
     };
-    
-    // Initialize the hashmap with the timeranges
-    // and the symbols
-    // Iterate over the symbols and create a hashmap for each symbol
-    // Then create a hashmap for each timerange in the symbol
-    // Add also volume entries to have a live volume
+
+    // The 1m candles of the oldest bucket in progress (e.g. since midnight with 1d)
+    let in_progress = timeranges.iter()
+        .map(|timerange| get_timerange(timerange, now).0)
+        .filter(|open_time| *open_time > 0)
+        .min()
+        .unwrap_or(now);
+
+    // And of the buckets not saved as closed yet, if they can be backfilled
+    let mut unclosed = HashMap::new();
+    if backfill.is_some() {
+        for symbol in &symbols {
+            unclosed.insert(symbol.clone(), unclosed_buckets(symbol, &timeranges, now).await?);
+        }
+    }
+    let since_of = |symbol: &str| unclosed.get(symbol)
+        .and_then(|buckets: &HashMap<String, i64>| buckets.values().min().copied())
+        .map_or(in_progress, |since| since.min(in_progress));
+
+    let since = symbols.iter().map(|symbol| since_of(symbol)).min().unwrap_or(in_progress);
+    let stored = store().load_recent(&symbols, "1m", since).await?;
+
     let mut new_entries = HashMap::new();
     for symbol in &symbols {
-        let mut map = timeranges
-            .iter()
-            .map(|t| (t.clone(), CandleOrValue::Candle(Candle::default())))
-            .collect::<HashMap<_, _>>();
-
-        map.insert("volume".to_string(), CandleOrValue::Value(0.0));
-        map.insert("usdt_volume".to_string(), CandleOrValue::Value(0.0));
-
-        new_entries.insert(symbol.clone(), map);
-    }
-
-    // Prepare the SQL query to fetch the last candles for the given symbols
-    // Timerange of 1m because we are using 1m candles (binance) 
-    // This should change depending on the provider
-    let query = "SELECT DISTINCT ON (symbol, timerange) * FROM candles WHERE symbol = ANY($1) ORDER BY symbol, timerange, open_time DESC";
-    let symbols: Vec<&str> = symbols.iter().map(|s| s.as_str()).collect();
-    let rows = client.query(query, &[&symbols]).await.expect("Failed to fetch last candles");
-
-    for last_candle in rows {
-        let symbol: String = last_candle.get("symbol");
-        let open_time: DateTime<Utc> = last_candle.get("open_time");
-        let open_time_unix: i64 = open_time.timestamp_millis();
-        let close_time: DateTime<Utc> = last_candle.get("close_time");
-        let close_time_unix: i64 = close_time.timestamp_millis();
-        let timerange: String = last_candle.get("timerange");
-        let open: f64 = last_candle.get("open");
-        let high: f64 = last_candle.get("high");
-        let low: f64 = last_candle.get("low");
-        let price: f64 = last_candle.get("close");
-        let volume: f64 = last_candle.get("volume");
-
-        // If the candle is in the hashmap, update it
-        // Other wise we just ignore it
-        if  let Some(CandleOrValue::Candle(candle)) = new_entries.get_mut(&symbol) .and_then(|m| m.get_mut(&timerange)) {
-            candle.open_time = open_time_unix;
-            candle.close_time = close_time_unix;
-            candle.open = open;
-            candle.high = high;
-            candle.low = low;
-            candle.price = Some(price);
-            candle.close = None;
-            candle.volume = volume;
+        let mut minutes: Vec<(Candle, Option<ExactValues>)> = stored.iter()
+            .filter(|candle| &candle.symbol == symbol)
+            .map(|candle| (candle.to_candle(), candle.exact.clone()))
+            .collect();
+
+        let backfilled = match &backfill {
+            Some(backfill) => backfill_minutes(symbol, &mut minutes, since_of(symbol), now, decimal, backfill).await,
+            None => false,
+        };
+
+        // Only with every minute of the downtime, the buckets would be incomplete otherwise
+        if let Some(unclosed) = unclosed.get(symbol).filter(|_| backfilled) {
+            let closed = closed_buckets(&timeranges, unclosed, &minutes, now, decimal);
+            match add_candles(&closed).await {
+                Ok(_) => info!(symbol, candles = closed.len(), "Candles closed during the downtime saved"),
+                Err(e) => warn!(symbol, error = %e, "Unable to save the candles closed during the downtime"),
+            }
         }
+
+        new_entries.insert(symbol.clone(), rebuild_entries(&timeranges, &minutes, now, decimal));
     }
 
     // Now we can update the CANDLES hashmap with the new entries
     let mut candles = CANDLES.lock().await;
     candles.extend(new_entries);
+
+    Ok(())
+}
+
+// The open time of the first bucket of each timerange (1m aside) not saved as closed yet
+// E.g. the hour in progress at shutdown, saved as partial, and the ones after it
+async fn unclosed_buckets(symbol: &str, timeranges: &[String], now: i64) -> Result<HashMap<String, i64>, StoreError> {
+    let mut buckets = HashMap::new();
+
+    let since = now - MAX_CATCH_UP_MS;
+    for timerange in timeranges.iter().filter(|timerange| *timerange != "1m") {
+        let in_progress = get_timerange(timerange, now).0;
+        let saved = store().load_recent(&[symbol.to_string()], timerange, since).await?;
+
+        // The bucket after the last closed one, or the first partial one
+        // Nothing to close if none was saved yet
+        let first = match saved.iter().rev().find(|candle| !candle.partial) {
+            Some(candle) => get_timerange(timerange, candle.close_time + 1_000).0,
+            None => saved.first().map_or(in_progress, |candle| candle.open_time),
+        };
+        buckets.insert(timerange.clone(), first.max(since).min(in_progress));
+    }
+
+    Ok(buckets)
+}
+
+// Fetch the 1m candles following the last closed one
+// A partial one (saved at shutdown) is fetched again, the last fetched one may still be in progress
+// Returns false if they could not be fetched
+async fn backfill_minutes(symbol: &str, minutes: &mut Vec<(Candle, Option<ExactValues>)>, since: i64, now: i64, decimal: bool, backfill: &Backfill<'_>) -> bool {
+    let from = minutes.iter()
+        .rev()
+        .find(|(candle, _)| candle.close.is_some())
+        .map_or(since, |(candle, _)| candle.open_time + 60_000);
+    if from > now {
+        return true;
+    }
+
+    let fetched = match fetch_1m_candles(backfill.provider, backfill.rest_url, symbol, from, now).await {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            warn!(symbol, error = %e, "Unable to backfill the candles");
+            return false;
+        }
+    };
+
+    // The closed ones are saved, like the live candles
+    let closed: Vec<StoredCandle> = fetched.iter()
+        .filter(|(candle, _)| candle.close_time < now)
+        .map(|(candle, exact)| StoredCandle::new(candle, decimal.then_some(exact), false))
+        .collect();
+    if let Err(e) = add_candles(&closed).await {
+        warn!(symbol, error = %e, "Unable to save the backfilled candles");
+    }
+
+    info!(symbol, candles = fetched.len(), "Candles backfilled");
+
+    minutes.retain(|(candle, _)| candle.open_time < from);
+    minutes.extend(fetched.into_iter().map(|(candle, exact)| (candle, decimal.then_some(exact))));
+
+    true
+}
+
+// The candles of the buckets closed since the first unclosed one of each timerange, until now
+// Closed like the aggregator does, with the open of the following minute as close
+fn closed_buckets(timeranges: &[String], unclosed: &HashMap<String, i64>, minutes: &[(Candle, Option<ExactValues>)], now: i64, decimal: bool) -> Vec<StoredCandle> {
+    let mut closed = Vec::new();
+
+    for timerange in timeranges {
+        let Some(&first) = unclosed.get(timerange) else {
+            continue;
+        };
+        let in_progress = get_timerange(timerange, now).0;
+
+        let mut buckets: BTreeMap<i64, Vec<&(Candle, Option<ExactValues>)>> = BTreeMap::new();
+        for minute in minutes.iter().filter(|(candle, _)| candle.open_time >= first && candle.open_time < in_progress) {
+            buckets.entry(get_timerange(timerange, minute.0.open_time).0).or_default().push(minute);
+        }
+
+        for (open_time, bucket) in buckets {
+            let (_, close_time) = get_timerange(timerange, open_time);
+            let (mut candle, mut exact) = aggregate(timerange, open_time, close_time, &bucket);
+
+            // Continuous with the next minute, as in proceed_data
+            let next = minutes.iter()
+                .find(|(candle, _)| candle.open_time > close_time)
+                .filter(|(next, _)| next.open_time - close_time <= 10_000);
+            if let Some((next, next_exact)) = next {
+                candle.close = Some(next.open);
+                if let (Some(exact), Some(next_exact)) = (exact.as_mut(), next_exact) {
+                    exact.close = Some(next_exact.open);
+                }
+            }
+
+            closed.push(StoredCandle::new(&candle, exact.as_ref().filter(|_| decimal), false));
+        }
+    }
+
+    closed
+}
+
+// The candle of a bucket from its 1m candles
+// With the exact values if every minute has them
+fn aggregate(timerange: &str, open_time: i64, close_time: i64, bucket: &[&(Candle, Option<ExactValues>)]) -> (Candle, Option<ExactValues>) {
+    let (first, _) = bucket[0];
+    let (last, _) = bucket[bucket.len() - 1];
+
+    let candle = Candle {
+        open_time,
+        close_time,
+        symbol: first.symbol.clone(),
+        timerange: timerange.to_string(),
+        open: first.open,
+        close: None,
+        high: bucket.iter().map(|(candle, _)| candle.high).fold(f64::MIN, f64::max),
+        low: bucket.iter().map(|(candle, _)| candle.low).fold(f64::MAX, f64::min),
+        price: last.price,
+        volume: bucket.iter().map(|(candle, _)| candle.volume).sum(),
+        usdt_volume: bucket.iter().map(|(candle, _)| candle.usdt_volume).sum(),
+    };
+
+    let exact: Option<Vec<&ExactValues>> = bucket.iter().map(|(_, exact)| exact.as_ref()).collect();
+    let exact = exact.map(|exact| ExactValues {
+        open: exact[0].open,
+        high: exact.iter().map(|values| values.high).max().unwrap_or_default(),
+        low: exact.iter().map(|values| values.low).min().unwrap_or_default(),
+        close: None,
+        price: exact[exact.len() - 1].price,
+        volume: exact.iter().map(|values| values.volume).sum(),
+        usdt_volume: exact.iter().map(|values| values.usdt_volume).sum(),
+    });
+
+    (candle, exact)
+}
+
+// The entries of a symbol, with the candle in progress of every timerange
+// The live volumes are the ones of the actual minute, so the next tick only adds its difference
+fn rebuild_entries(timeranges: &[String], minutes: &[(Candle, Option<ExactValues>)], now: i64, decimal: bool) -> HashMap<String, CandleOrValue> {
+    let mut entries = empty_entries(timeranges);
+    let mut state = ExactState::default();
+
+    for timerange in timeranges {
+        // A bucket without any minute stays empty, the next tick opens it
+        let (open_time, close_time) = get_timerange(timerange, now);
+        let bucket: Vec<&(Candle, Option<ExactValues>)> = minutes.iter()
+            .filter(|(candle, _)| candle.open_time >= open_time && candle.open_time < close_time)
+            .collect();
+        if bucket.is_empty() {
+            continue;
+        }
+
+        let (candle, exact) = aggregate(timerange, open_time, close_time, &bucket);
+
+        // Only if every minute has its exact values
+        if decimal && exact.is_none() {
+            // The candle goes on without exact values until its bucket closes
+            let missing = bucket.iter().filter(|(_, exact)| exact.is_none()).count();
+            warn!(symbol = %candle.symbol, %timerange, missing, minutes = bucket.len(), "Minutes without exact values, the candle in progress is not rebuilt in decimal mode");
+        }
+        if let Some(values) = exact.filter(|_| decimal) {
+            state.open(timerange, &values);
+        }
+        entries.insert(timerange.clone(), CandleOrValue::Candle(candle));
+    }
+
+    // The live volumes of the actual minute, if we have it
+    let actual_minute = get_timerange("1m", now).0;
+    if let Some((candle, exact)) = minutes.last().filter(|(candle, _)| candle.open_time == actual_minute) {
+        entries.insert("volume".to_string(), CandleOrValue::Value(candle.volume));
+        entries.insert("usdt_volume".to_string(), CandleOrValue::Value(candle.usdt_volume));
+
+        if let Some(exact) = exact {
+            state.volume = exact.volume;
+            state.usdt_volume = exact.usdt_volume;
+        }
+    }
+
+    if decimal {
+        entries.insert(EXACT_KEY.to_string(), CandleOrValue::Exact(state));
+    }
+
+    entries
 }
 
-pub async fn add_candle(candle: &Candle) {
-    let client = get_db_client().await;
-    let client = client.lock().await;
+// Fill the history with the last closed candles of every timerange
+// So the consumers that need it are ready right after the startup
+pub async fn load_history(symbols: &[String], limit: usize) -> Result<(), StoreError> {
+    HISTORY.lock().await.set_capacity(limit);
+    if limit == 0 {
+        return Ok(());
+    }
+
+    let timeranges = TIMERANGES.lock().await.clone();
+    let candles = store().load_history(symbols, &timeranges, limit).await?;
+
+    let mut history = HISTORY.lock().await;
+    for candle in &candles {
+        history.push(candle.to_candle());
+    }
+
+    info!(candles = candles.len(), "History loaded");
+
+    Ok(())
+}
+
+// The last closed candles of a symbol and timerange opened before a time (ms), oldest first
+// From the history when it holds enough of them, from the store otherwise
+pub async fn history_page(symbol: &str, timerange: &str, before_ms: Option<i64>, limit: usize) -> Result<Vec<Candle>, StoreError> {
+    {
+        let history = HISTORY.lock().await;
+        let candles: Vec<Candle> = history.get(symbol, timerange, None)
+            .into_iter()
+            .filter(|candle| before_ms.is_none_or(|before| candle.open_time < before))
+            .collect();
+
+        if candles.len() >= limit {
+            return Ok(candles[candles.len() - limit..].to_vec());
+        }
+    }
 
-    // Convert the open and close times to timestamps
-    // The timestamps are in milliseconds, so we divide by 1000
-    let open_time = DateTime::<Utc>::from_timestamp(candle.open_time / 1000, 0).unwrap();
-    let close_time = DateTime::<Utc>::from_timestamp(candle.close_time / 1000, 0).unwrap();
+    let candles = store().load_before(symbol, timerange, before_ms, limit).await?;
 
-    // We are sure that the close price is not None
-    // So we can unwrap it
-    let usdt_volume = candle.volume * candle.close.unwrap();
+    Ok(candles.iter().map(StoredCandle::to_candle).collect())
+}
+
+// Record an interval without updates for a symbol
+// So the missing candles can be backfilled later
+pub async fn add_outage(symbol: &str, since_ms: i64, until_ms: i64) -> Result<(), StoreError> {
+    store().add_outage(symbol, since_ms, until_ms).await
+}
+
+// Keep a candle that failed the validation
+// So it can be inspected later
+pub async fn quarantine_candle(candle: &Candle, rule: Rule) -> Result<(), StoreError> {
+    // Nowhere to keep it before the store is opened
+    match STORE.get() {
+        Some(store) => store.quarantine_candle(candle, rule).await,
+        None => Ok(()),
+    }
+}
+
+// Check that the store answers
+pub async fn ping() -> bool {
+    store().ping().await
+}
+
+// Save a closed candle
+// With its exact values in decimal mode
+pub async fn add_candle(candle: &Candle, exact: Option<&ExactValues>) {
+    upsert_candle(candle, exact, false).await;
+}
+
+// Save a candle that is not closed yet (e.g. at shutdown)
+// It will be overwritten once the candle is closed
+pub async fn add_partial_candle(candle: &Candle, exact: Option<&ExactValues>) {
+    upsert_candle(candle, exact, true).await;
+}
+
+#[instrument(level = "debug", skip_all, fields(symbol = %candle.symbol, timerange = %candle.timerange, partial))]
+async fn upsert_candle(candle: &Candle, exact: Option<&ExactValues>, partial: bool) {
+    if !PERSISTENCE.load(Ordering::Relaxed) {
+        return;
+    }
+
+    // Written by write_candles, so the aggregation never waits for the database
+    keep_pending(StoredCandle::new(candle, exact, partial));
+    QUEUED.notify_one();
+}
 
-    // Prepare the SQL query to insert the candle into the database
-    let query = "INSERT INTO candles (symbol, timerange, open_time, close_time, open, high, low, close, volume, usdt_volume) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) ON CONFLICT (symbol, timerange, open_time) DO UPDATE SET high = EXCLUDED.high, low = EXCLUDED.low, close = EXCLUDED.close, volume = EXCLUDED.volume, usdt_volume = EXCLUDED.usdt_volume;";
-    client.execute(query, &[&candle.symbol, &candle.timerange, &open_time, &close_time, &candle.open, &candle.high, &candle.low, &candle.price, &candle.volume, &usdt_volume]).await.expect("Failed to insert candle into the database");
+// Most candles kept while the database can't be written to
+// About a week of 1m candles for a symbol, the oldest ones are dropped after it
+const MAX_PENDING_WRITES: usize = 10_000;
 
-}
\ No newline at end of file
+// Wait between two attempts while the database can't be written to
+const RETRY_MIN_BACKOFF: Duration = Duration::from_millis(100);
+const RETRY_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+// The candles waiting to be written, oldest first, with an id to recognize them
+// At most one per symbol, timerange and open time (the last version)
+static PENDING: Lazy<std::sync::Mutex<VecDeque<(u64, StoredCandle)>>> = Lazy::new(|| std::sync::Mutex::new(VecDeque::new()));
+static PENDING_ID: AtomicU64 = AtomicU64::new(0);
+
+// Notified when a candle is queued
+static QUEUED: Lazy<Notify> = Lazy::new(Notify::new);
+
+// Set while the writes fail, so the failure and the recovery are logged once
+static WRITES_FAILING: AtomicBool = AtomicBool::new(false);
+
+fn keep_pending(candle: StoredCandle) {
+    let mut pending = PENDING.lock().unwrap();
+    pending.retain(|(_, other)| (&other.symbol, &other.timerange, other.open_time) != (&candle.symbol, &candle.timerange, candle.open_time));
+    if pending.len() >= MAX_PENDING_WRITES {
+        if let Some((_, dropped)) = pending.pop_front() {
+            error!(symbol = %dropped.symbol, timerange = %dropped.timerange, open_time = dropped.open_time, "Too many pending writes, the oldest candle is lost");
+        }
+    }
+    pending.push_back((PENDING_ID.fetch_add(1, Ordering::Relaxed), candle));
+
+    METRICS.db_writes_pending.set(pending.len() as i64);
+}
+
+// Write the queued candles in the background, until the service stops
+// Retried with a backoff while the database can't be written to
+pub async fn write_candles() {
+    let mut shutdown = shutdown::subscribe();
+    let mut backoff = RETRY_MIN_BACKOFF;
+
+    loop {
+        if write_pending().await == 0 {
+            backoff = RETRY_MIN_BACKOFF;
+            tokio::select! {
+                _ = QUEUED.notified() => continue,
+                _ = shutdown::wait(&mut shutdown) => return,
+            }
+        }
+
+        // The database is unavailable, wait before trying again
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => (),
+            _ = shutdown::wait(&mut shutdown) => return,
+        }
+        backoff = (backoff * 2).min(RETRY_MAX_BACKOFF);
+    }
+}
+
+// Write the queued candles, oldest first
+// Stops at the first failure, the database is probably unavailable
+// Returns the number of candles still pending
+pub async fn write_pending() -> usize {
+    loop {
+        let Some((id, candle)) = PENDING.lock().unwrap().front().cloned() else {
+            return 0;
+        };
+
+        let started = Instant::now();
+        if let Err(e) = store().upsert_candle(&candle).await {
+            METRICS.db_write_failures.inc(&candle.timerange);
+            let pending = PENDING.lock().unwrap().len();
+            if !WRITES_FAILING.swap(true, Ordering::Relaxed) {
+                error!(error = %e, pending, "Unable to save the candles, they will be retried");
+            } else {
+                debug!(error = %e, pending, "Candle writes still failing");
+            }
+            return pending;
+        }
+        METRICS.db_write_duration.observe(started.elapsed());
+
+        if WRITES_FAILING.swap(false, Ordering::Relaxed) {
+            info!("Candle writes recovered");
+        }
+
+        // Unless a newer version was queued meanwhile
+        let mut pending = PENDING.lock().unwrap();
+        if pending.front().is_some_and(|(front, _)| *front == id) {
+            pending.pop_front();
+        }
+        METRICS.db_writes_pending.set(pending.len() as i64);
+    }
+}
+
+// Save many candles at once (e.g. import)
+// Same values as upsert_candle, so importing twice gives the same rows
+#[instrument(level = "debug", skip_all, fields(count = candles.len()))]
+pub async fn add_candles(candles: &[StoredCandle]) -> Result<u64, StoreError> {
+    let started = Instant::now();
+    let written = store().upsert_candles(candles).await?;
+
+    METRICS.db_write_duration.observe(started.elapsed());
+
+    Ok(written)
+}
+
+// Stream the candles of a symbol and timerange, oldest first
+pub async fn stream_candles(symbol: &str, timerange: &str, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<CandleStream, StoreError> {
+    store().range(symbol, timerange, from, to).await
+}
+
+// Recompute what the store derives from the 1m candles over a range of open times (ms)
+// E.g. the continuous aggregates of the TimescaleDB mode
+pub async fn refresh_derived(from_ms: i64, to_ms: i64) -> Result<(), StoreError> {
+    store().refresh_derived(from_ms, to_ms).await
+}
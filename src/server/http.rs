@@ -0,0 +1,64 @@
+use crate::server::{api, database, shutdown};
+use crate::utils::metrics::METRICS;
+
+use axum::{Json, Router, http::StatusCode, response::IntoResponse, routing::get};
+use serde_json::json;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tracing::error;
+
+// Maximum time to check that the database is reachable
+pub const DB_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Serve the health, readiness and metrics endpoints
+// And the REST API
+// The errors are returned to the supervisor, which restarts the server
+pub async fn serve_http(address: String) -> std::io::Result<()> {
+    let app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(metrics))
+        .merge(api::router());
+
+    let listener = TcpListener::bind(&address)
+        .await
+        .inspect_err(|e| error!(error = %e, address, "Unable to bind HTTP listener"))?;
+
+    let mut shutdown = shutdown::subscribe();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move { shutdown::wait(&mut shutdown).await })
+        .await
+        .inspect_err(|e| error!(error = %e, "HTTP server error"))
+}
+
+// The process is alive
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+// The service is able to serve data
+async fn readyz() -> impl IntoResponse {
+    let provider_connected = METRICS.provider_connected.get() == 1;
+    let warmup_done = METRICS.warmup_done.load(Ordering::Relaxed);
+    let database_reachable = tokio::time::timeout(DB_CHECK_TIMEOUT, database::ping())
+        .await
+        .unwrap_or(false);
+
+    let ready = provider_connected && warmup_done && database_reachable;
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+
+    (status, Json(json!({
+        "ready": ready,
+        "provider_connected": provider_connected,
+        "database_reachable": database_reachable,
+        "warmup_done": warmup_done,
+    })))
+}
+
+async fn metrics() -> impl IntoResponse {
+    (
+        [("content-type", "text/plain; version=0.0.4")],
+        METRICS.render(),
+    )
+}
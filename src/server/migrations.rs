@@ -0,0 +1,206 @@
+use crate::utils::config::DatabaseConfig;
+
+use deadpool_postgres::{Pool, PoolError};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use tokio_postgres::Client;
+use tracing::info;
+
+// A versioned change of the schema
+// Embedded in the binary, so a fresh database only needs `core migrate`
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+    // Some statements can't run in a transaction (e.g. the continuous aggregates)
+    // They are then run one by one, split on the semicolons
+    pub transactional: bool,
+}
+
+impl Migration {
+    // Detects a migration edited after being applied
+    fn checksum(&self) -> String {
+        Sha256::digest(self.sql.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn statements(&self) -> Vec<String> {
+        let sql = self.sql.lines()
+            .filter(|line| !line.trim_start().starts_with("--"))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        sql.split(';')
+            .map(|statement| statement.trim().to_string())
+            .filter(|statement| !statement.is_empty())
+            .collect()
+    }
+}
+
+// Every migration, in order
+// Never edit an applied migration, add a new one instead
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "create_candles", sql: include_str!("../../migrations/0001_create_candles.sql"), transactional: true },
+    Migration { version: 2, name: "add_candles_partial", sql: include_str!("../../migrations/0002_add_candles_partial.sql"), transactional: true },
+    Migration { version: 3, name: "create_feed_outages", sql: include_str!("../../migrations/0003_create_feed_outages.sql"), transactional: true },
+    Migration { version: 4, name: "create_quarantined_candles", sql: include_str!("../../migrations/0004_create_quarantined_candles.sql"), transactional: true },
+];
+
+// Applied after the others in TimescaleDB mode
+pub const TIMESCALE_MIGRATIONS: &[Migration] = &[
+    Migration { version: 1001, name: "timescale_hypertable", sql: include_str!("../../migrations/1001_timescale_hypertable.sql"), transactional: true },
+    Migration { version: 1002, name: "timescale_continuous_aggregates", sql: include_str!("../../migrations/1002_timescale_continuous_aggregates.sql"), transactional: false },
+];
+
+// The continuous aggregates refresh the last 7 days at most
+// The 1m candles must be kept longer, or the aggregates would lose them
+pub const TIMESCALE_REFRESH_DAYS: u32 = 7;
+
+// Applied migrations, created by the first run
+const CREATE_HISTORY: &str = "CREATE TABLE IF NOT EXISTS schema_migrations (version BIGINT PRIMARY KEY, name TEXT NOT NULL, checksum TEXT NOT NULL, applied_at TIMESTAMPTZ NOT NULL DEFAULT now())";
+const INSERT_HISTORY: &str = "INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3)";
+
+// Only one instance migrates at a time, the others wait for it
+const MIGRATION_LOCK: i64 = 0x636c_7573_7465_7278;
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Database(tokio_postgres::Error),
+    Pool(PoolError),
+    // An applied migration is not the same anymore
+    Modified { version: i64, name: String },
+    // The database was migrated by a newer version
+    Unknown { version: i64, name: String },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // The server message says which statement failed (e.g. a missing extension)
+            MigrationError::Database(e) => match e.as_db_error() {
+                Some(db_error) => write!(f, "Unable to migrate the database: {}", db_error),
+                None => write!(f, "Unable to migrate the database: {}", e),
+            },
+            MigrationError::Pool(e) => write!(f, "Unable to connect to the database: {}", e),
+            MigrationError::Modified { version, name } => write!(f, "Migration {} ({}) was modified after being applied", version, name),
+            MigrationError::Unknown { version, name } => write!(f, "Migration {} ({}) is applied but unknown to this version", version, name),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MigrationError::Database(e) => Some(e),
+            MigrationError::Pool(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<tokio_postgres::Error> for MigrationError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        MigrationError::Database(e)
+    }
+}
+
+impl From<PoolError> for MigrationError {
+    fn from(e: PoolError) -> Self {
+        MigrationError::Pool(e)
+    }
+}
+
+// Apply the pending migrations, each in its own transaction
+// With dry_run, nothing is changed and the pending migrations are only returned
+// In TimescaleDB mode, the compression and retention policies are applied too
+pub async fn migrate(pool: &Pool, config: &DatabaseConfig, dry_run: bool) -> Result<Vec<&'static Migration>, MigrationError> {
+    let mut client = pool.get().await?;
+
+    // A session lock, since some migrations run outside of a transaction
+    client.execute("SELECT pg_advisory_lock($1)", &[&MIGRATION_LOCK]).await?;
+    // The IF NOT EXISTS notices would be logged on every startup
+    client.batch_execute("SET client_min_messages = warning").await?;
+
+    let result = apply_migrations(&mut client, config, dry_run).await;
+
+    client.batch_execute("RESET client_min_messages").await?;
+    client.execute("SELECT pg_advisory_unlock($1)", &[&MIGRATION_LOCK]).await?;
+
+    result
+}
+
+async fn apply_migrations(client: &mut Client, config: &DatabaseConfig, dry_run: bool) -> Result<Vec<&'static Migration>, MigrationError> {
+    // Nothing is created in dry run mode
+    let has_history: bool = client.query_one("SELECT to_regclass('schema_migrations') IS NOT NULL", &[]).await?.get(0);
+    if !has_history && !dry_run {
+        client.batch_execute(CREATE_HISTORY).await?;
+    }
+
+    let applied: HashMap<i64, (String, String)> = if has_history || !dry_run {
+        client.query("SELECT version, name, checksum FROM schema_migrations", &[]).await?
+            .into_iter()
+            .map(|row| (row.get("version"), (row.get("name"), row.get("checksum"))))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    // The applied migrations must match ours
+    // The TimescaleDB ones are known even if the mode was disabled since
+    for (version, (name, checksum)) in &applied {
+        match MIGRATIONS.iter().chain(TIMESCALE_MIGRATIONS).find(|m| m.version == *version) {
+            Some(migration) if migration.checksum() == *checksum => (),
+            Some(_) => return Err(MigrationError::Modified { version: *version, name: name.clone() }),
+            None => return Err(MigrationError::Unknown { version: *version, name: name.clone() }),
+        }
+    }
+
+    let timescale: &[Migration] = if config.timescale { TIMESCALE_MIGRATIONS } else { &[] };
+    let pending: Vec<&Migration> = MIGRATIONS.iter().chain(timescale).filter(|m| !applied.contains_key(&m.version)).collect();
+
+    if dry_run {
+        return Ok(pending);
+    }
+
+    for migration in &pending {
+        if migration.transactional {
+            let transaction = client.transaction().await?;
+            transaction.batch_execute(migration.sql).await?;
+            transaction.execute(INSERT_HISTORY, &[&migration.version, &migration.name, &migration.checksum()]).await?;
+            transaction.commit().await?;
+        } else {
+            for statement in migration.statements() {
+                client.batch_execute(&statement).await?;
+            }
+            client.execute(INSERT_HISTORY, &[&migration.version, &migration.name, &migration.checksum()]).await?;
+        }
+
+        info!(version = migration.version, name = migration.name, "Migration applied");
+    }
+
+    if config.timescale {
+        apply_timescale_policies(client, config).await?;
+    }
+
+    Ok(pending)
+}
+
+// The policies depend on the config, so they are replaced at every startup
+// 0 disables a policy
+async fn apply_timescale_policies(client: &Client, config: &DatabaseConfig) -> Result<(), tokio_postgres::Error> {
+    client.batch_execute("SELECT remove_compression_policy('candles', if_exists => TRUE)").await?;
+    if config.compress_after_days > 0 {
+        let days = config.compress_after_days as i32;
+        client.execute("SELECT add_compression_policy('candles', compress_after => make_interval(days => $1))", &[&days]).await?;
+    }
+
+    client.batch_execute("SELECT remove_retention_policy('candles', if_exists => TRUE)").await?;
+    if config.retention_days > 0 {
+        let days = config.retention_days as i32;
+        client.execute("SELECT add_retention_policy('candles', drop_after => make_interval(days => $1))", &[&days]).await?;
+    }
+
+    info!(compress_after_days = config.compress_after_days, retention_days = config.retention_days, "TimescaleDB policies applied");
+
+    Ok(())
+}
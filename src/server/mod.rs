@@ -1,2 +1,9 @@
+pub mod api;
 pub mod database;
+pub mod http;
+pub mod migrations;
+pub mod shutdown;
+pub mod store;
+pub mod supervisor;
+pub mod watchdog;
 pub mod websocket;
\ No newline at end of file
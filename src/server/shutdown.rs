@@ -0,0 +1,109 @@
+use crate::handler::candle::{CANDLES, CandleOrValue};
+use crate::handler::decimal::EXACT_KEY;
+use crate::providers::recorder;
+use crate::server::{database, websocket};
+
+use once_cell::sync::Lazy;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tracing::{error, info};
+
+// Exit codes of the process
+// So the orchestrator knows why we stopped
+pub const EXIT_OK: i32 = 0;
+// A task stopped on its own (e.g. the provider connection was lost)
+pub const EXIT_FAILURE: i32 = 1;
+// The shutdown did not complete before the deadline
+pub const EXIT_DEADLINE_EXCEEDED: i32 = 2;
+
+// Set to true once the shutdown has started
+// Every long running task listens to it to stop cleanly
+static SHUTDOWN: Lazy<watch::Sender<bool>> = Lazy::new(|| watch::channel(false).0);
+
+// Get a receiver to be notified of the shutdown
+pub fn subscribe() -> watch::Receiver<bool> {
+    SHUTDOWN.subscribe()
+}
+
+// Ask every task to stop
+pub fn trigger() {
+    SHUTDOWN.send_replace(true);
+}
+
+// Wait until the given receiver sees the shutdown
+pub async fn wait(receiver: &mut watch::Receiver<bool>) {
+    let _ = receiver.wait_for(|stopping| *stopping).await;
+}
+
+// Wait for Ctrl-C or SIGTERM
+pub async fn wait_for_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Unable to listen to SIGTERM");
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("Ctrl-C received, shutting down"),
+        _ = terminate.recv() => info!("SIGTERM received, shutting down"),
+    }
+}
+
+// Stop the service cleanly
+// Stop the ingestion, save the open candles and close the clients
+// Returns the exit code of the process
+pub async fn graceful_shutdown(deadline: Duration, exit_code: i32) -> i32 {
+    trigger();
+
+    match tokio::time::timeout(deadline, flush()).await {
+        Ok(()) => {
+            info!("Shutdown complete");
+            exit_code
+        },
+        Err(_) => {
+            error!(?deadline, "Shutdown did not complete within the deadline");
+            EXIT_DEADLINE_EXCEEDED
+        }
+    }
+}
+
+async fn flush() {
+    // Tell the clients we are going away
+    // First, so they are told even if the database holds the rest of the shutdown
+    websocket::close_clients().await;
+
+    // The lock is kept until the end
+    // So no new tick can modify the candles we are saving
+    let candles = CANDLES.lock().await;
+
+    // Save every in-progress candle as partial
+    // So we don't lose the data received since the last close
+    let mut saved = 0;
+    for timeranges in candles.values() {
+        // The exact values of the symbol (decimal mode only)
+        let exact = match timeranges.get(EXACT_KEY) {
+            Some(CandleOrValue::Exact(state)) => Some(state),
+            _ => None,
+        };
+
+        for (timerange, value) in timeranges.iter() {
+            if let CandleOrValue::Candle(candle) = value {
+                // Empty candles (no tick received yet) are skipped
+                if candle.open_time == 0 {
+                    continue;
+                }
+
+                let exact = exact.and_then(|state| state.candles.get(timerange));
+                database::add_partial_candle(candle, exact).await;
+                saved += 1;
+            }
+        }
+    }
+    info!(saved, "Saved open candles");
+
+    // Write them with the ones still queued
+    // Stops at the first failure, so an unavailable database doesn't hold the shutdown
+    let pending = database::write_pending().await;
+    if pending > 0 {
+        error!(pending, "Candles could not be saved");
+    }
+
+    recorder::flush();
+}
@@ -0,0 +1,125 @@
+use crate::handler::validation::Rule;
+use super::{CandleStore, CandleStream, StoreError, StoredCandle};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use common::Candle;
+use futures_util::{StreamExt, stream};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+// The candles of each symbol and timerange, by open time
+type Series = BTreeMap<(String, String), BTreeMap<i64, StoredCandle>>;
+
+// The candles in memory, lost at exit
+// For the replay and the tests, where nothing must be kept
+#[derive(Default)]
+pub struct MemoryStore {
+    candles: Mutex<Series>,
+    outages: Mutex<Vec<(String, i64, i64)>>,
+    quarantined: Mutex<Vec<(Candle, Rule)>>,
+}
+
+impl MemoryStore {
+    // Intervals without updates, as (symbol, since, until)
+    pub fn outages(&self) -> Vec<(String, i64, i64)> {
+        self.outages.lock().unwrap().clone()
+    }
+
+    pub fn quarantined(&self) -> Vec<(Candle, Rule)> {
+        self.quarantined.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl CandleStore for MemoryStore {
+    async fn upsert_candle(&self, candle: &StoredCandle) -> Result<(), StoreError> {
+        let mut candles = self.candles.lock().unwrap();
+        candles.entry((candle.symbol.clone(), candle.timerange.clone()))
+            .or_default()
+            .insert(candle.open_time, candle.clone());
+
+        Ok(())
+    }
+
+    async fn load_recent(&self, symbols: &[String], timerange: &str, since_ms: i64) -> Result<Vec<StoredCandle>, StoreError> {
+        let candles = self.candles.lock().unwrap();
+
+        Ok(symbols.iter()
+            .filter_map(|symbol| candles.get(&(symbol.clone(), timerange.to_string())))
+            .flat_map(|series| series.range(since_ms..).map(|(_, candle)| candle.clone()))
+            .collect())
+    }
+
+    async fn load_history(&self, symbols: &[String], timeranges: &[String], limit: usize) -> Result<Vec<StoredCandle>, StoreError> {
+        let candles = self.candles.lock().unwrap();
+        let now = Utc::now().timestamp_millis();
+
+        let mut history = Vec::new();
+        for symbol in symbols {
+            for timerange in timeranges {
+                let Some(series) = candles.get(&(symbol.clone(), timerange.clone())) else {
+                    continue;
+                };
+
+                let mut closed: Vec<StoredCandle> = series.values().rev()
+                    .filter(|candle| !candle.partial && candle.close_time < now)
+                    .take(limit)
+                    .cloned()
+                    .collect();
+                closed.reverse();
+                history.extend(closed);
+            }
+        }
+
+        Ok(history)
+    }
+
+    async fn load_before(&self, symbol: &str, timerange: &str, before_ms: Option<i64>, limit: usize) -> Result<Vec<StoredCandle>, StoreError> {
+        let candles = self.candles.lock().unwrap();
+        let now = Utc::now().timestamp_millis();
+
+        let Some(series) = candles.get(&(symbol.to_string(), timerange.to_string())) else {
+            return Ok(Vec::new());
+        };
+
+        let mut page: Vec<StoredCandle> = series.range(..before_ms.unwrap_or(i64::MAX)).rev()
+            .map(|(_, candle)| candle)
+            .filter(|candle| !candle.partial && candle.close_time < now)
+            .take(limit)
+            .cloned()
+            .collect();
+        page.reverse();
+
+        Ok(page)
+    }
+
+    // A copy of the range, the store can be written while it is read
+    async fn range(&self, symbol: &str, timerange: &str, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<CandleStream, StoreError> {
+        let from = from.map_or(i64::MIN, |from| from.timestamp_millis());
+        let to = to.map_or(i64::MAX, |to| to.timestamp_millis());
+
+        let candles: Vec<StoredCandle> = match self.candles.lock().unwrap().get(&(symbol.to_string(), timerange.to_string())) {
+            Some(series) if from < to => series.range(from..to).map(|(_, candle)| candle.clone()).collect(),
+            _ => Vec::new(),
+        };
+
+        Ok(stream::iter(candles.into_iter().map(Ok)).boxed())
+    }
+
+    async fn quarantine_candle(&self, candle: &Candle, rule: Rule) -> Result<(), StoreError> {
+        self.quarantined.lock().unwrap().push((candle.clone(), rule));
+
+        Ok(())
+    }
+
+    async fn add_outage(&self, symbol: &str, since_ms: i64, until_ms: i64) -> Result<(), StoreError> {
+        self.outages.lock().unwrap().push((symbol.to_string(), since_ms, until_ms));
+
+        Ok(())
+    }
+
+    async fn ping(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,182 @@
+pub mod memory;
+pub mod postgres;
+pub mod sqlite;
+
+use crate::handler::decimal::ExactValues;
+use crate::handler::validation::Rule;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use common::Candle;
+use futures_util::stream::BoxStream;
+use serde::Serialize;
+use std::fmt;
+
+// A candle as saved in the store
+#[derive(Clone, Debug, Serialize)]
+pub struct StoredCandle {
+    pub symbol: String,
+    pub timerange: String,
+    pub open_time: i64,
+    pub close_time: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+    pub usdt_volume: f64,
+    pub partial: bool,
+    // The same values as decimals, when they are known exactly
+    // The price is the close
+    #[serde(skip)]
+    pub exact: Option<ExactValues>,
+}
+
+impl StoredCandle {
+    // The values saved for a candle of the aggregator
+    // A partial candle (or one after a gap) has no close yet, so the usdt volume uses the actual price
+    pub fn new(candle: &Candle, exact: Option<&ExactValues>, partial: bool) -> Self {
+        let price = candle.price.unwrap_or(candle.open);
+        let close = if partial { price } else { candle.close.unwrap_or(price) };
+
+        let exact = exact.map(|exact| {
+            let close = if partial { exact.price } else { exact.close.unwrap_or(exact.price) };
+
+            ExactValues {
+                close: None,
+                usdt_volume: exact.volume * close,
+                ..exact.clone()
+            }
+        });
+
+        StoredCandle {
+            symbol: candle.symbol.clone(),
+            timerange: candle.timerange.clone(),
+            open_time: candle.open_time,
+            close_time: candle.close_time,
+            open: candle.open,
+            high: candle.high,
+            low: candle.low,
+            close: price,
+            volume: candle.volume,
+            usdt_volume: candle.volume * close,
+            partial,
+            exact,
+        }
+    }
+
+    // The candle as the aggregator sends it
+    pub fn to_candle(&self) -> Candle {
+        Candle {
+            open_time: self.open_time,
+            close_time: self.close_time,
+            symbol: self.symbol.clone(),
+            timerange: self.timerange.clone(),
+            open: self.open,
+            close: (!self.partial).then_some(self.close),
+            high: self.high,
+            low: self.low,
+            price: Some(self.close),
+            volume: self.volume,
+            usdt_volume: self.usdt_volume,
+        }
+    }
+}
+
+pub type CandleStream = BoxStream<'static, Result<StoredCandle, StoreError>>;
+
+#[derive(Debug)]
+pub enum StoreError {
+    Postgres(tokio_postgres::Error),
+    // No connection could be taken from the pool (e.g. the server is down)
+    Pool(deadpool_postgres::PoolError),
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Postgres(e) => match e.as_db_error() {
+                Some(db_error) => write!(f, "postgres: {}", db_error),
+                None => write!(f, "postgres: {}", e),
+            },
+            StoreError::Pool(e) => write!(f, "postgres pool: {}", e),
+            StoreError::Sqlite(e) => write!(f, "sqlite: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::Postgres(e) => Some(e),
+            StoreError::Pool(e) => Some(e),
+            StoreError::Sqlite(e) => Some(e),
+        }
+    }
+}
+
+impl From<tokio_postgres::Error> for StoreError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        StoreError::Postgres(e)
+    }
+}
+
+impl From<deadpool_postgres::PoolError> for StoreError {
+    fn from(e: deadpool_postgres::PoolError) -> Self {
+        StoreError::Pool(e)
+    }
+}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Sqlite(e)
+    }
+}
+
+// Where the candles are saved
+// Every write is an upsert on (symbol, timerange, open time), so writing twice gives the same rows
+#[async_trait]
+pub trait CandleStore: Send + Sync {
+    async fn upsert_candle(&self, candle: &StoredCandle) -> Result<(), StoreError>;
+
+    // Save many candles at once (e.g. import)
+    // Returns the number of candles written
+    async fn upsert_candles(&self, candles: &[StoredCandle]) -> Result<u64, StoreError> {
+        for candle in candles {
+            self.upsert_candle(candle).await?;
+        }
+
+        Ok(candles.len() as u64)
+    }
+
+    // The candles of a timerange opened since a time (ms), with their exact values
+    // Oldest first for each symbol
+    async fn load_recent(&self, symbols: &[String], timerange: &str, since_ms: i64) -> Result<Vec<StoredCandle>, StoreError>;
+
+    // The last closed candles of every timerange of the symbols, at most `limit` per timerange
+    // Oldest first for each symbol and timerange
+    async fn load_history(&self, symbols: &[String], timeranges: &[String], limit: usize) -> Result<Vec<StoredCandle>, StoreError>;
+
+    // The last closed candles of a symbol and timerange opened before a time (ms), at most `limit`
+    // The last closed ones if no time is given, oldest first
+    async fn load_before(&self, symbol: &str, timerange: &str, before_ms: Option<i64>, limit: usize) -> Result<Vec<StoredCandle>, StoreError>;
+
+    // The candles of a symbol and timerange, oldest first
+    // The bounds are optional, the open time is in [from, to)
+    async fn range(&self, symbol: &str, timerange: &str, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<CandleStream, StoreError>;
+
+    // Keep a candle that failed the validation
+    async fn quarantine_candle(&self, candle: &Candle, rule: Rule) -> Result<(), StoreError>;
+
+    // Record an interval without updates for a symbol (ms)
+    async fn add_outage(&self, symbol: &str, since_ms: i64, until_ms: i64) -> Result<(), StoreError>;
+
+    // Recompute what the store derives from the 1m candles (e.g. after an import)
+    async fn refresh_derived(&self, _from_ms: i64, _to_ms: i64) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    // Check that the store answers
+    async fn ping(&self) -> bool;
+}
@@ -0,0 +1,308 @@
+use crate::handler::decimal::ExactValues;
+use crate::handler::validation::Rule;
+use crate::utils::config::DatabaseConfig;
+use crate::utils::metrics::METRICS;
+use super::{CandleStore, CandleStream, StoreError, StoredCandle};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use common::Candle;
+use deadpool_postgres::{Manager, ManagerConfig, Object, Pool, RecyclingMethod, Runtime};
+use futures_util::{SinkExt, StreamExt};
+use rust_decimal::Decimal;
+use std::fmt::Write;
+use std::time::Duration;
+use tokio_postgres::{NoTls, Row};
+use tokio_postgres::types::ToSql;
+
+// The connections to Postgres
+// Nothing is connected until a connection is needed
+pub fn create_pool(config: &DatabaseConfig) -> Result<Pool, StoreError> {
+    let pg_config: tokio_postgres::Config = config.url.parse()?;
+
+    // A connection is checked before being reused
+    // So the ones broken by a server restart (or failover) are replaced by new ones
+    let manager_config = ManagerConfig { recycling_method: RecyclingMethod::Verified };
+    let manager = Manager::from_config(pg_config, NoTls, manager_config);
+
+    let timeout = Some(Duration::from_secs(config.pool_timeout_secs));
+    let pool = Pool::builder(manager)
+        .max_size(config.pool_size)
+        .wait_timeout(timeout)
+        .create_timeout(timeout)
+        .recycle_timeout(timeout)
+        .runtime(Runtime::Tokio1)
+        .build()
+        .expect("A runtime is set for the pool timeouts");
+
+    Ok(pool)
+}
+
+// The candles in Postgres, through a pool of connections
+pub struct PostgresStore {
+    pool: Pool,
+    // In TimescaleDB mode only the 1m candles are written
+    // The other timeranges are derived by the continuous aggregates
+    timescale: bool,
+}
+
+impl PostgresStore {
+    pub fn new(pool: Pool, timescale: bool) -> Self {
+        PostgresStore { pool, timescale }
+    }
+
+    // Wait for a free connection, or a new one
+    async fn client(&self) -> Result<Object, StoreError> {
+        METRICS.db_pool_waiting.inc();
+        let client = self.pool.get().await;
+        METRICS.db_pool_waiting.dec();
+
+        let status = self.pool.status();
+        METRICS.db_pool_connections.set(status.size as i64);
+        METRICS.db_pool_available.set(status.available as i64);
+
+        Ok(client?)
+    }
+
+    // Whether this candle is stored, or derived by the database
+    fn is_stored(&self, candle: &StoredCandle) -> bool {
+        !self.timescale || candle.timerange == "1m"
+    }
+}
+
+// The values are cast in the queries
+// So the columns can either be double precision or NUMERIC
+const UPSERT_FLOAT: &str = "INSERT INTO candles (symbol, timerange, open_time, close_time, open, high, low, close, volume, usdt_volume, partial) VALUES ($1, $2, $3, $4, $5::float8, $6::float8, $7::float8, $8::float8, $9::float8, $10::float8, $11) ON CONFLICT (symbol, timerange, open_time) DO UPDATE SET high = EXCLUDED.high, low = EXCLUDED.low, close = EXCLUDED.close, volume = EXCLUDED.volume, usdt_volume = EXCLUDED.usdt_volume, partial = EXCLUDED.partial;";
+const UPSERT_EXACT: &str = "INSERT INTO candles (symbol, timerange, open_time, close_time, open, high, low, close, volume, usdt_volume, partial) VALUES ($1, $2, $3, $4, $5::numeric, $6::numeric, $7::numeric, $8::numeric, $9::numeric, $10::numeric, $11) ON CONFLICT (symbol, timerange, open_time) DO UPDATE SET high = EXCLUDED.high, low = EXCLUDED.low, close = EXCLUDED.close, volume = EXCLUDED.volume, usdt_volume = EXCLUDED.usdt_volume, partial = EXCLUDED.partial;";
+
+// The bulk rows are copied in a staging table, then upserted like the live candles
+// NUMERIC fits both the double precision and NUMERIC columns
+const CREATE_STAGING: &str = "CREATE TEMP TABLE candles_staging (symbol TEXT, timerange TEXT, open_time TIMESTAMPTZ, close_time TIMESTAMPTZ, open NUMERIC, high NUMERIC, low NUMERIC, close NUMERIC, volume NUMERIC, usdt_volume NUMERIC, partial BOOLEAN) ON COMMIT DROP";
+const COPY_STAGING: &str = "COPY candles_staging FROM STDIN (FORMAT csv)";
+const UPSERT_STAGING: &str = "INSERT INTO candles (symbol, timerange, open_time, close_time, open, high, low, close, volume, usdt_volume, partial) SELECT symbol, timerange, open_time, close_time, open, high, low, close, volume, usdt_volume, partial FROM candles_staging ON CONFLICT (symbol, timerange, open_time) DO UPDATE SET high = EXCLUDED.high, low = EXCLUDED.low, close = EXCLUDED.close, volume = EXCLUDED.volume, usdt_volume = EXCLUDED.usdt_volume, partial = EXCLUDED.partial;";
+
+// The columns of a candle
+// The values are read both as float8 and numeric, whatever the type of the columns
+macro_rules! candle_columns {
+    () => {
+        "symbol, timerange, open_time, close_time, \
+        open::float8 AS open, high::float8 AS high, low::float8 AS low, close::float8 AS close, volume::float8 AS volume, \
+        usdt_volume::float8 AS usdt_volume, partial, \
+        open::numeric AS exact_open, high::numeric AS exact_high, low::numeric AS exact_low, close::numeric AS exact_close, \
+        volume::numeric AS exact_volume, usdt_volume::numeric AS exact_usdt_volume"
+    };
+}
+
+// The recent candles of a timerange
+const SELECT_RECENT: &str = concat!("SELECT ", candle_columns!(), " \
+    FROM candles WHERE symbol = ANY($1) AND timerange = $2 AND open_time >= $3 ORDER BY symbol, open_time");
+
+// Continuous aggregates of the TimescaleDB mode (see the migrations)
+const CONTINUOUS_AGGREGATES: &[&str] = &["candles_5m", "candles_15m", "candles_30m", "candles_1h", "candles_4h", "candles_1d"];
+
+// Convert the open and close times to timestamps
+// The timestamps are in milliseconds, so we divide by 1000
+fn timestamp(time_ms: i64) -> DateTime<Utc> {
+    DateTime::<Utc>::from_timestamp(time_ms / 1000, 0).unwrap()
+}
+
+// The exact values are read too, so the decimal mode keeps them up to the export
+fn stored_candle(row: &Row) -> StoredCandle {
+    let open_time: DateTime<Utc> = row.get("open_time");
+    let close_time: DateTime<Utc> = row.get("close_time");
+
+    let exact = Some(ExactValues {
+        open: row.get("exact_open"),
+        high: row.get("exact_high"),
+        low: row.get("exact_low"),
+        close: None,
+        price: row.get::<_, Option<Decimal>>("exact_close").unwrap_or_default(),
+        volume: row.get("exact_volume"),
+        usdt_volume: row.get("exact_usdt_volume"),
+    });
+
+    StoredCandle {
+        symbol: row.get("symbol"),
+        timerange: row.get("timerange"),
+        open_time: open_time.timestamp_millis(),
+        close_time: close_time.timestamp_millis(),
+        open: row.get("open"),
+        high: row.get("high"),
+        low: row.get("low"),
+        close: row.get::<_, Option<f64>>("close").unwrap_or_default(),
+        volume: row.get("volume"),
+        usdt_volume: row.get("usdt_volume"),
+        partial: row.get("partial"),
+        exact,
+    }
+}
+
+#[async_trait]
+impl CandleStore for PostgresStore {
+    async fn upsert_candle(&self, candle: &StoredCandle) -> Result<(), StoreError> {
+        if !self.is_stored(candle) {
+            return Ok(());
+        }
+
+        let client = self.client().await?;
+
+        let open_time = timestamp(candle.open_time);
+        let close_time = timestamp(candle.close_time);
+
+        // The upserts are prepared once per connection
+        match &candle.exact {
+            Some(exact) => {
+                let statement = client.prepare_cached(UPSERT_EXACT).await?;
+                client.execute(&statement, &[&candle.symbol, &candle.timerange, &open_time, &close_time, &exact.open, &exact.high, &exact.low, &exact.price, &exact.volume, &exact.usdt_volume, &candle.partial]).await?
+            },
+            None => {
+                let statement = client.prepare_cached(UPSERT_FLOAT).await?;
+                client.execute(&statement, &[&candle.symbol, &candle.timerange, &open_time, &close_time, &candle.open, &candle.high, &candle.low, &candle.close, &candle.volume, &candle.usdt_volume, &candle.partial]).await?
+            }
+        };
+
+        Ok(())
+    }
+
+    // With COPY, through a staging table
+    async fn upsert_candles(&self, candles: &[StoredCandle]) -> Result<u64, StoreError> {
+        let mut csv = String::new();
+        for candle in candles.iter().filter(|candle| self.is_stored(candle)) {
+            let (open, high, low, close, volume, usdt_volume) = match &candle.exact {
+                Some(exact) => (exact.open.to_string(), exact.high.to_string(), exact.low.to_string(), exact.price.to_string(), exact.volume.to_string(), exact.usdt_volume.to_string()),
+                None => (candle.open.to_string(), candle.high.to_string(), candle.low.to_string(), candle.close.to_string(), candle.volume.to_string(), candle.usdt_volume.to_string()),
+            };
+
+            let _ = writeln!(csv, "{},{},{},{},{},{},{},{},{},{},{}", candle.symbol, candle.timerange, timestamp(candle.open_time).to_rfc3339(), timestamp(candle.close_time).to_rfc3339(), open, high, low, close, volume, usdt_volume, candle.partial);
+        }
+
+        if csv.is_empty() {
+            return Ok(0);
+        }
+
+        let mut client = self.client().await?;
+        let transaction = client.transaction().await?;
+        transaction.batch_execute(CREATE_STAGING).await?;
+
+        let sink = transaction.copy_in(COPY_STAGING).await?;
+        futures_util::pin_mut!(sink);
+        sink.send(Bytes::from(csv)).await?;
+        sink.finish().await?;
+
+        let written = transaction.execute(UPSERT_STAGING, &[]).await?;
+        transaction.commit().await?;
+
+        Ok(written)
+    }
+
+    async fn load_recent(&self, symbols: &[String], timerange: &str, since_ms: i64) -> Result<Vec<StoredCandle>, StoreError> {
+        let client = self.client().await?;
+
+        let since = DateTime::<Utc>::from_timestamp_millis(since_ms).unwrap();
+        let rows = client.query(SELECT_RECENT, &[&symbols, &timerange, &since]).await?;
+
+        Ok(rows.iter().map(stored_candle).collect())
+    }
+
+    // An index scan per symbol and timerange, whatever the size of the table
+    // In TimescaleDB mode, the in-progress buckets of the continuous aggregates are left out by their close time
+    async fn load_history(&self, symbols: &[String], timeranges: &[String], limit: usize) -> Result<Vec<StoredCandle>, StoreError> {
+        let source = if self.timescale { "candle_series" } else { "candles" };
+        let query = format!("SELECT c.* FROM unnest($1::text[]) AS s (symbol) CROSS JOIN unnest($2::text[]) AS t (timerange) \
+            CROSS JOIN LATERAL (SELECT {} \
+                FROM {} WHERE symbol = s.symbol AND timerange = t.timerange AND NOT partial AND close_time < now() \
+                ORDER BY open_time DESC LIMIT $3) AS c \
+            ORDER BY c.symbol, c.timerange, c.open_time", candle_columns!(), source);
+
+        let client = self.client().await?;
+        let rows = client.query(&query, &[&symbols, &timeranges, &(limit as i64)]).await?;
+
+        Ok(rows.iter().map(stored_candle).collect())
+    }
+
+    async fn load_before(&self, symbol: &str, timerange: &str, before_ms: Option<i64>, limit: usize) -> Result<Vec<StoredCandle>, StoreError> {
+        let source = if self.timescale { "candle_series" } else { "candles" };
+        let query = format!("SELECT {} \
+            FROM {} WHERE symbol = $1 AND timerange = $2 AND NOT partial AND close_time < now() \
+            AND ($3::timestamptz IS NULL OR open_time < $3) \
+            ORDER BY open_time DESC LIMIT $4", candle_columns!(), source);
+
+        let client = self.client().await?;
+        let before = before_ms.and_then(DateTime::<Utc>::from_timestamp_millis);
+        let rows = client.query(&query, &[&symbol, &timerange, &before, &(limit as i64)]).await?;
+
+        Ok(rows.iter().rev().map(stored_candle).collect())
+    }
+
+    // The rows are fetched as they are read, so any range fits in memory
+    // In TimescaleDB mode, the candles come from the view over the continuous aggregates
+    async fn range(&self, symbol: &str, timerange: &str, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<CandleStream, StoreError> {
+        let source = if self.timescale { "candle_series" } else { "candles" };
+        let query = format!("SELECT {} \
+            FROM {} WHERE symbol = $1 AND timerange = $2 \
+            AND ($3::timestamptz IS NULL OR open_time >= $3) AND ($4::timestamptz IS NULL OR open_time < $4) \
+            ORDER BY open_time", candle_columns!(), source);
+
+        let client = self.client().await?;
+        let params: [&(dyn ToSql + Sync); 4] = [&symbol, &timerange, &from, &to];
+        let rows = client.query_raw(&query, params).await?;
+
+        // The connection goes back to the pool once the stream is dropped
+        Ok(rows.map(move |row| {
+            let _client = &client;
+            Ok(stored_candle(&row?))
+        }).boxed())
+    }
+
+    async fn quarantine_candle(&self, candle: &Candle, rule: Rule) -> Result<(), StoreError> {
+        let client = self.client().await?;
+
+        let open_time = DateTime::<Utc>::from_timestamp_millis(candle.open_time).unwrap_or_default();
+        let payload = serde_json::to_value(candle).unwrap();
+
+        let query = "INSERT INTO quarantined_candles (symbol, timerange, open_time, rule, payload) VALUES ($1, $2, $3, $4, $5)";
+        client.execute(query, &[&candle.symbol, &candle.timerange, &open_time, &rule.as_str(), &payload]).await?;
+
+        Ok(())
+    }
+
+    async fn add_outage(&self, symbol: &str, since_ms: i64, until_ms: i64) -> Result<(), StoreError> {
+        let client = self.client().await?;
+
+        let started_at = DateTime::<Utc>::from_timestamp_millis(since_ms).unwrap();
+        let ended_at = DateTime::<Utc>::from_timestamp_millis(until_ms).unwrap();
+
+        let query = "INSERT INTO feed_outages (symbol, started_at, ended_at) VALUES ($1, $2, $3)";
+        client.execute(query, &[&symbol, &started_at, &ended_at]).await?;
+
+        Ok(())
+    }
+
+    // Materialize the continuous aggregates over a range of open times
+    // The policies only refresh the last days, so older candles (e.g. imported) need it
+    async fn refresh_derived(&self, from_ms: i64, to_ms: i64) -> Result<(), StoreError> {
+        if !self.timescale {
+            return Ok(());
+        }
+
+        let client = self.client().await?;
+
+        let from = DateTime::<Utc>::from_timestamp_millis(from_ms).unwrap();
+        let to = DateTime::<Utc>::from_timestamp_millis(to_ms).unwrap();
+
+        for aggregate in CONTINUOUS_AGGREGATES {
+            let query = format!("CALL refresh_continuous_aggregate('{}', $1::timestamptz, $2::timestamptz)", aggregate);
+            client.execute(&query, &[&from, &to]).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn ping(&self) -> bool {
+        match self.client().await {
+            Ok(client) => client.simple_query("SELECT 1").await.is_ok(),
+            Err(_) => false,
+        }
+    }
+}
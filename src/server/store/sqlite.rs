@@ -0,0 +1,313 @@
+use crate::handler::decimal::ExactValues;
+use crate::handler::validation::Rule;
+use super::{CandleStore, CandleStream, StoreError, StoredCandle};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use common::Candle;
+use futures_util::{StreamExt, stream};
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use rusqlite::types::Type;
+use rust_decimal::Decimal;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+// The candles in a single SQLite file
+// For a deployment without Postgres (e.g. a laptop or a small VPS)
+// The values are saved as text, so the decimal mode keeps them exact
+#[derive(Clone)]
+pub struct SqliteStore {
+    connection: Arc<Mutex<Connection>>,
+}
+
+// Same tables as the Postgres migrations, the times are in ms
+const CREATE_SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    PRAGMA synchronous = NORMAL;
+    CREATE TABLE IF NOT EXISTS candles (
+        symbol TEXT NOT NULL,
+        timerange TEXT NOT NULL,
+        open_time INTEGER NOT NULL,
+        close_time INTEGER NOT NULL,
+        open TEXT NOT NULL,
+        high TEXT NOT NULL,
+        low TEXT NOT NULL,
+        close TEXT NOT NULL,
+        volume TEXT NOT NULL,
+        usdt_volume TEXT NOT NULL,
+        partial INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (symbol, timerange, open_time)
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS feed_outages (
+        id INTEGER PRIMARY KEY,
+        symbol TEXT NOT NULL,
+        started_at INTEGER NOT NULL,
+        ended_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS quarantined_candles (
+        id INTEGER PRIMARY KEY,
+        symbol TEXT NOT NULL,
+        timerange TEXT NOT NULL,
+        open_time INTEGER NOT NULL,
+        rule TEXT NOT NULL,
+        payload TEXT NOT NULL,
+        received_at INTEGER NOT NULL
+    );";
+
+const UPSERT: &str = "INSERT INTO candles (symbol, timerange, open_time, close_time, open, high, low, close, volume, usdt_volume, partial) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11) ON CONFLICT (symbol, timerange, open_time) DO UPDATE SET high = excluded.high, low = excluded.low, close = excluded.close, volume = excluded.volume, usdt_volume = excluded.usdt_volume, partial = excluded.partial";
+
+// The recent candles of a symbol and timerange
+const SELECT_RECENT: &str = "SELECT symbol, timerange, open_time, close_time, open, high, low, close, volume, usdt_volume, partial FROM candles \
+    WHERE symbol = ?1 AND timerange = ?2 AND open_time >= ?3 ORDER BY open_time";
+
+// The last closed candles of a symbol and timerange, newest first
+const SELECT_HISTORY: &str = "SELECT symbol, timerange, open_time, close_time, open, high, low, close, volume, usdt_volume, partial FROM candles \
+    WHERE symbol = ?1 AND timerange = ?2 AND partial = 0 AND close_time < ?3 \
+    ORDER BY open_time DESC LIMIT ?4";
+
+// The last closed candles opened before a time, newest first
+const SELECT_BEFORE: &str = "SELECT symbol, timerange, open_time, close_time, open, high, low, close, volume, usdt_volume, partial FROM candles \
+    WHERE symbol = ?1 AND timerange = ?2 AND partial = 0 AND close_time < ?3 AND open_time < ?4 \
+    ORDER BY open_time DESC LIMIT ?5";
+
+// The bounds are optional, the open time is in [from, to)
+const SELECT_RANGE: &str = "SELECT symbol, timerange, open_time, close_time, open, high, low, close, volume, usdt_volume, partial FROM candles \
+    WHERE symbol = ?1 AND timerange = ?2 AND open_time >= ?3 AND (?4 IS NULL OR open_time < ?4) \
+    ORDER BY open_time LIMIT ?5";
+
+// Rows read at once by range, the connection is released between the pages
+const PAGE_SIZE: i64 = 1000;
+
+impl SqliteStore {
+    // Create the file and the tables if needed
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(CREATE_SCHEMA)?;
+
+        Ok(SqliteStore { connection: Arc::new(Mutex::new(connection)) })
+    }
+
+    // rusqlite is blocking, so the queries run outside of the runtime threads
+    async fn run<T, F>(&self, query: F) -> Result<T, StoreError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+
+        let result = tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().unwrap();
+            query(&mut connection)
+        }).await.expect("SQLite task panicked");
+
+        Ok(result?)
+    }
+}
+
+fn upsert(connection: &Connection, candle: &StoredCandle) -> rusqlite::Result<()> {
+    let values: [String; 6] = match &candle.exact {
+        Some(exact) => [exact.open, exact.high, exact.low, exact.price, exact.volume, exact.usdt_volume].map(|value| value.to_string()),
+        None => [candle.open, candle.high, candle.low, candle.close, candle.volume, candle.usdt_volume].map(|value| value.to_string()),
+    };
+    let [open, high, low, close, volume, usdt_volume] = values;
+
+    let mut statement = connection.prepare_cached(UPSERT)?;
+    statement.execute(params![candle.symbol, candle.timerange, candle.open_time, candle.close_time, open, high, low, close, volume, usdt_volume, candle.partial])?;
+
+    Ok(())
+}
+
+fn conversion_error(index: usize, e: impl std::error::Error + Send + Sync + 'static) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(e))
+}
+
+fn float(row: &Row, index: usize) -> rusqlite::Result<f64> {
+    row.get::<_, String>(index)?.parse().map_err(|e| conversion_error(index, e))
+}
+
+fn decimal(row: &Row, index: usize) -> rusqlite::Result<Decimal> {
+    let value: String = row.get(index)?;
+    Decimal::from_str(&value).or_else(|_| Decimal::from_scientific(&value)).map_err(|e| conversion_error(index, e))
+}
+
+fn stored_candle(row: &Row) -> rusqlite::Result<StoredCandle> {
+    let exact = Some(ExactValues {
+        open: decimal(row, 4)?,
+        high: decimal(row, 5)?,
+        low: decimal(row, 6)?,
+        close: None,
+        price: decimal(row, 7)?,
+        volume: decimal(row, 8)?,
+        usdt_volume: decimal(row, 9)?,
+    });
+
+    Ok(StoredCandle {
+        symbol: row.get(0)?,
+        timerange: row.get(1)?,
+        open_time: row.get(2)?,
+        close_time: row.get(3)?,
+        open: float(row, 4)?,
+        high: float(row, 5)?,
+        low: float(row, 6)?,
+        close: float(row, 7)?,
+        volume: float(row, 8)?,
+        usdt_volume: float(row, 9)?,
+        partial: row.get(10)?,
+        exact,
+    })
+}
+
+#[async_trait]
+impl CandleStore for SqliteStore {
+    async fn upsert_candle(&self, candle: &StoredCandle) -> Result<(), StoreError> {
+        let candle = candle.clone();
+
+        self.run(move |connection| upsert(connection, &candle)).await
+    }
+
+    // In a single transaction, SQLite syncs once
+    async fn upsert_candles(&self, candles: &[StoredCandle]) -> Result<u64, StoreError> {
+        let candles = candles.to_vec();
+
+        self.run(move |connection| {
+            let transaction = connection.transaction()?;
+            for candle in &candles {
+                upsert(&transaction, candle)?;
+            }
+            transaction.commit()?;
+
+            Ok(candles.len() as u64)
+        }).await
+    }
+
+    async fn load_recent(&self, symbols: &[String], timerange: &str, since_ms: i64) -> Result<Vec<StoredCandle>, StoreError> {
+        let symbols = symbols.to_vec();
+        let timerange = timerange.to_string();
+
+        self.run(move |connection| {
+            let mut statement = connection.prepare(SELECT_RECENT)?;
+
+            let mut candles = Vec::new();
+            for symbol in &symbols {
+                for candle in statement.query_map(params![symbol, timerange, since_ms], stored_candle)? {
+                    candles.push(candle?);
+                }
+            }
+
+            Ok(candles)
+        }).await
+    }
+
+    async fn load_history(&self, symbols: &[String], timeranges: &[String], limit: usize) -> Result<Vec<StoredCandle>, StoreError> {
+        let symbols = symbols.to_vec();
+        let timeranges = timeranges.to_vec();
+        let now = Utc::now().timestamp_millis();
+
+        self.run(move |connection| {
+            let mut statement = connection.prepare(SELECT_HISTORY)?;
+
+            let mut candles = Vec::new();
+            for symbol in &symbols {
+                for timerange in &timeranges {
+                    let rows = statement.query_map(params![symbol, timerange, now, limit as i64], stored_candle)?;
+                    let mut series = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+                    series.reverse();
+                    candles.extend(series);
+                }
+            }
+
+            Ok(candles)
+        }).await
+    }
+
+    async fn load_before(&self, symbol: &str, timerange: &str, before_ms: Option<i64>, limit: usize) -> Result<Vec<StoredCandle>, StoreError> {
+        let symbol = symbol.to_string();
+        let timerange = timerange.to_string();
+        let before = before_ms.unwrap_or(i64::MAX);
+        let now = Utc::now().timestamp_millis();
+
+        self.run(move |connection| {
+            let mut statement = connection.prepare_cached(SELECT_BEFORE)?;
+            let rows = statement.query_map(params![symbol, timerange, now, before, limit as i64], stored_candle)?;
+            let mut candles = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+            candles.reverse();
+
+            Ok(candles)
+        }).await
+    }
+
+    // Read by pages, from the open time following the last row read
+    async fn range(&self, symbol: &str, timerange: &str, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<CandleStream, StoreError> {
+        let store = self.clone();
+        let symbol = symbol.to_string();
+        let timerange = timerange.to_string();
+        let to = to.map(|to| to.timestamp_millis());
+        let from = from.map_or(i64::MIN, |from| from.timestamp_millis());
+
+        let pages = stream::unfold(Some(from), move |cursor| {
+            let store = store.clone();
+            let symbol = symbol.clone();
+            let timerange = timerange.clone();
+
+            async move {
+                let cursor = cursor?;
+                let page = store.run(move |connection| {
+                    let mut statement = connection.prepare_cached(SELECT_RANGE)?;
+                    let rows = statement.query_map(params![symbol, timerange, cursor, to, PAGE_SIZE], stored_candle)?;
+                    rows.collect::<rusqlite::Result<Vec<_>>>()
+                }).await;
+
+                let next = match &page {
+                    Ok(candles) if candles.len() as i64 == PAGE_SIZE => candles.last().map(|candle| candle.open_time + 1),
+                    _ => None,
+                };
+
+                Some((page, next))
+            }
+        });
+
+        Ok(pages.flat_map(|page| {
+            let candles = match page {
+                Ok(candles) => candles.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            };
+
+            stream::iter(candles)
+        }).boxed())
+    }
+
+    async fn quarantine_candle(&self, candle: &Candle, rule: Rule) -> Result<(), StoreError> {
+        let symbol = candle.symbol.clone();
+        let timerange = candle.timerange.clone();
+        let open_time = candle.open_time;
+        let payload = serde_json::to_string(candle).unwrap();
+        let received_at = Utc::now().timestamp_millis();
+
+        self.run(move |connection| {
+            let query = "INSERT INTO quarantined_candles (symbol, timerange, open_time, rule, payload, received_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+            connection.execute(query, params![symbol, timerange, open_time, rule.as_str(), payload, received_at])?;
+
+            Ok(())
+        }).await
+    }
+
+    async fn add_outage(&self, symbol: &str, since_ms: i64, until_ms: i64) -> Result<(), StoreError> {
+        let symbol = symbol.to_string();
+
+        self.run(move |connection| {
+            let query = "INSERT INTO feed_outages (symbol, started_at, ended_at) VALUES (?1, ?2, ?3)";
+            connection.execute(query, params![symbol, since_ms, until_ms])?;
+
+            Ok(())
+        }).await
+    }
+
+    async fn ping(&self) -> bool {
+        let result = self.run(|connection| {
+            connection.query_row("SELECT 1", [], |row| row.get::<_, i64>(0)).optional()
+        }).await;
+
+        matches!(result, Ok(Some(1)))
+    }
+}
@@ -0,0 +1,155 @@
+use crate::server::shutdown;
+use crate::utils::config::SupervisorConfig;
+
+use futures_util::future::BoxFuture;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, Notify};
+use tracing::{error, info, warn};
+
+// State of a supervised subsystem
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubsystemState {
+    Running,
+    Restarting,
+    // Exceeded its restart budget
+    Failed,
+    // Stopped because the service is shutting down
+    Stopped,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SubsystemStatus {
+    pub state: SubsystemState,
+    pub critical: bool,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+}
+
+// Status of every subsystem, by name
+static STATUSES: Lazy<Mutex<BTreeMap<String, SubsystemStatus>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+// Notified when a critical subsystem exceeded its restart budget
+static FATAL: Lazy<Notify> = Lazy::new(Notify::new);
+
+// Get the status of every subsystem
+pub async fn statuses() -> BTreeMap<String, SubsystemStatus> {
+    STATUSES.lock().await.clone()
+}
+
+// Wait until a critical subsystem can't be restarted anymore
+// The process should then exit so the orchestrator can react
+pub async fn wait_for_fatal() {
+    FATAL.notified().await;
+}
+
+// How a subsystem stopped, as recorded in its status
+pub trait Exit {
+    fn describe(self) -> String;
+}
+
+impl Exit for () {
+    fn describe(self) -> String {
+        "returned".to_string()
+    }
+}
+
+// A subsystem can return its error instead of panicking
+impl<E: std::fmt::Display> Exit for Result<(), E> {
+    fn describe(self) -> String {
+        match self {
+            Ok(()) => "returned".to_string(),
+            Err(e) => e.to_string(),
+        }
+    }
+}
+
+// Run a subsystem and restart it with a backoff when it panics, fails or returns
+// A subsystem that fails more than `max_restarts` times within the window is given up
+pub fn supervise<F, T>(name: &str, critical: bool, config: SupervisorConfig, factory: F)
+where
+    F: Fn() -> BoxFuture<'static, T> + Send + Sync + 'static,
+    T: Exit + Send + 'static,
+{
+    let name = name.to_string();
+
+    tokio::spawn(async move {
+        let mut shutdown = shutdown::subscribe();
+        let mut failures: VecDeque<Instant> = VecDeque::new();
+        let mut restarts = 0;
+
+        loop {
+            set_status(&name, SubsystemState::Running, critical, restarts, None).await;
+
+            // Run the subsystem in its own task
+            // So a panic is caught as a JoinError instead of killing the supervisor
+            let task = tokio::spawn(factory());
+            let error = tokio::select! {
+                result = task => match result {
+                    Ok(exit) => exit.describe(),
+                    Err(e) if e.is_panic() => "panicked".to_string(),
+                    Err(e) => e.to_string(),
+                },
+                _ = shutdown::wait(&mut shutdown) => {
+                    set_status(&name, SubsystemState::Stopped, critical, restarts, None).await;
+                    return;
+                }
+            };
+
+            // The subsystems are expected to return when shutting down
+            if *shutdown.borrow() {
+                set_status(&name, SubsystemState::Stopped, critical, restarts, None).await;
+                return;
+            }
+
+            warn!(subsystem = %name, %error, "Subsystem stopped");
+
+            // Only keep the failures within the window
+            let now = Instant::now();
+            failures.push_back(now);
+            while failures.front().is_some_and(|t| now.duration_since(*t) > Duration::from_secs(config.window_secs)) {
+                failures.pop_front();
+            }
+
+            if failures.len() as u32 > config.max_restarts {
+                error!(subsystem = %name, max_restarts = config.max_restarts, window_secs = config.window_secs, "Subsystem exceeded its restart budget");
+                set_status(&name, SubsystemState::Failed, critical, restarts, Some(error)).await;
+
+                if critical {
+                    FATAL.notify_one();
+                }
+
+                return;
+            }
+
+            // Exponential backoff depending on the recent failures
+            let exponent = (failures.len() as u32).saturating_sub(1).min(16);
+            let backoff = Duration::from_millis(config.initial_backoff_ms.saturating_mul(1 << exponent))
+                .min(Duration::from_millis(config.max_backoff_ms));
+
+            restarts += 1;
+            set_status(&name, SubsystemState::Restarting, critical, restarts, Some(error)).await;
+            info!(subsystem = %name, ?backoff, "Restarting subsystem");
+
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => (),
+                _ = shutdown::wait(&mut shutdown) => {
+                    set_status(&name, SubsystemState::Stopped, critical, restarts, None).await;
+                    return;
+                }
+            }
+        }
+    });
+}
+
+async fn set_status(name: &str, state: SubsystemState, critical: bool, restarts: u32, last_error: Option<String>) {
+    let mut statuses = STATUSES.lock().await;
+
+    // Keep the last error until a new one happens
+    let last_error = last_error.or_else(|| statuses.get(name).and_then(|s| s.last_error.clone()));
+
+    statuses.insert(name.to_string(), SubsystemStatus { state, critical, restarts, last_error });
+}
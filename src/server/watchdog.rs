@@ -0,0 +1,113 @@
+use crate::CONFIG;
+use crate::server::{database, websocket::{RECONNECT, send_message_to_clients}};
+use crate::utils::metrics::METRICS;
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+// Symbols whose feed is stale
+// The value is the last time (ms) we received an update for the symbol
+static STALE: Lazy<Mutex<HashMap<String, i64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Check periodically that every symbol receives updates
+// A stale symbol forces a reconnection to the provider
+pub async fn watch_feeds() {
+    let (stale_after, check_interval) = {
+        let config = CONFIG.get().unwrap().lock().await;
+        (
+            Duration::from_secs(config.watchdog.stale_after_secs).as_millis() as i64,
+            Duration::from_secs(config.watchdog.check_interval_secs),
+        )
+    };
+
+    let mut interval = tokio::time::interval(check_interval);
+
+    loop {
+        interval.tick().await;
+
+        // Nothing to check while we are not connected
+        if METRICS.provider_connected.get() == 0 {
+            continue;
+        }
+
+        let symbols = CONFIG.get().unwrap().lock().await.params.symbols.clone();
+        let connected_at = METRICS.provider_connected_at.load(Ordering::Relaxed);
+        let now = Utc::now().timestamp_millis();
+
+        let mut reconnect = false;
+        for symbol in symbols {
+            // A symbol that never received an update since the connection
+            // is checked against the connection time
+            let last_update = METRICS.last_tick(&symbol).unwrap_or(0);
+            let reference = last_update.max(connected_at);
+
+            if now - reference <= stale_after {
+                continue;
+            }
+
+            // Only notify once per outage
+            // But keep reconnecting while it lasts
+            reconnect = true;
+            let since = if last_update > 0 { last_update } else { connected_at };
+            let newly_stale = STALE.lock().unwrap().insert(symbol.clone(), since).is_none();
+
+            if newly_stale {
+                warn!(symbol = %symbol, since, "Feed is stale");
+                send_status(&symbol, "stale", since).await;
+            }
+        }
+
+        if reconnect {
+            info!("Forcing a reconnection to the provider because of stale feeds");
+            RECONNECT.notify_one();
+        }
+    }
+}
+
+// Called for every update of a symbol
+// Closes the outage of the symbol if it was stale
+pub fn record_tick(symbol: &str) {
+    let since = match STALE.lock().unwrap().remove(symbol) {
+        Some(since) => since,
+        None => return,
+    };
+
+    let symbol = symbol.to_string();
+    let until = Utc::now().timestamp_millis();
+
+    // Don't slow down the aggregation
+    tokio::spawn(async move {
+        info!(symbol = %symbol, since, until, "Feed recovered");
+        send_status(&symbol, "recovered", until).await;
+
+        // Keep the outage so the missing candles can be backfilled
+        if let Err(e) = database::add_outage(&symbol, since, until).await {
+            error!(symbol = %symbol, error = %e, "Unable to record the outage");
+        }
+    });
+}
+
+// Whether the feed of a symbol is stale
+pub fn is_stale(symbol: &str) -> bool {
+    STALE.lock().unwrap().contains_key(symbol)
+}
+
+// Tell the clients about the state of a feed
+async fn send_status(symbol: &str, status: &str, time: i64) {
+    let message = json!({
+        "type": "status",
+        "value": {
+            "symbol": symbol,
+            "status": status,
+            "time": time,
+        }
+    });
+
+    send_message_to_clients(&message.to_string()).await;
+}
@@ -1,52 +1,307 @@
-use crate::handler::candle::proceed_data;
+use crate::handler::candle::{CANDLES, CandleOrValue, proceed_data};
+use crate::handler::history::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
 use crate::CONFIG;
+use crate::utils::config::WebsocketConfig;
 use crate::providers;
+use crate::providers::message::ProviderMessage;
+use crate::providers::recorder;
+use crate::server::{database, shutdown};
+use crate::utils::metrics::METRICS;
 
+use chrono::Utc;
+use common::{Candle, TIMERANGES};
 use futures_util::{SinkExt, StreamExt, stream::SplitSink};
 use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify, mpsc};
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::net::{TcpStream, TcpListener};
+use tokio::task::JoinHandle;
+use tracing::{Instrument, debug, debug_span, error, info, warn};
 use tokio_tungstenite::{accept_async, connect_async, tungstenite::Message, WebSocketStream};
+use tokio_tungstenite::tungstenite::{self, Utf8Bytes, protocol::{CloseFrame, frame::coding::CloseCode}};
 
-pub static CLIENTS: Lazy<Arc<Mutex<Vec<Arc<Mutex<SplitSink<WebSocketStream<TcpStream>, Message>>>>>>> = Lazy::new(|| Arc::new(Mutex::new(Vec::new())));
+// A stream of candles, as (symbol, timerange)
+type StreamKey = (String, String);
 
-pub async fn connect_to_provider_websocket() {
-    // Load the configuration
-    // To create the WebSocket connection
-    let config = CONFIG.get().unwrap().lock().await.clone();
-    let url = providers::general::build_stream_url(&config.stream.provider, &config.params.symbols, &config.stream.stream_type);
+type Sink = SplitSink<WebSocketStream<TcpStream>, Message>;
 
-    // Connect to the WebSocket stream
-    let (provider_ws_stream, _) = connect_async(&url)
-        .await
-        .expect("Failed to connect to Binance");
+// A client of the intra websocket
+pub struct Client {
+    // Locked while its messages are queued, so they keep the order of their streams
+    state: std::sync::Mutex<ClientState>,
+}
 
-    let (mut provider_write, mut provider_read) = provider_ws_stream.split();
+struct ClientState {
+    // The streams whose candles are sent to the client
+    subscriptions: HashSet<StreamKey>,
+    // The queue of the connection, written by its own task, None once the client is dropped
+    // So a slow client never holds the aggregator
+    outbox: Option<mpsc::Sender<Message>>,
+    writer: Option<JoinHandle<()>>,
+}
 
-    // Wait for messages from the WebSocket stream
-    // and get the candle data
-    // Or handle ping/pong messages
-    while let Some(message) = provider_read.next().await {
-        match message {
-            Ok(Message::Text(text)) => {
-                let candle = providers::general::parse_candle(&text, &config.stream.provider).unwrap();
+impl ClientState {
+    // Queue a message for the connection, if it is still there
+    // A client that doesn't empty its queue is dropped
+    fn queue(&mut self, message: Message) {
+        let Some(outbox) = &self.outbox else {
+            return;
+        };
 
-                proceed_data(candle).await;
+        match outbox.try_send(message) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                warn!("The queue of a client is full, dropping its connection");
+                self.outbox = None;
             },
-            Ok(Message::Ping(ping)) => {
-                // Handle ping messages
-                provider_write.send(Message::Pong(ping)).await.unwrap();
-            },
-            Ok(_) => (),
-            Err(e) => {
-                eprintln!("Error: {}", e);
+            // Already dropped by its writer
+            Err(TrySendError::Closed(_)) => self.outbox = None,
+        }
+    }
+
+    fn queue_text(&mut self, text: impl Into<Utf8Bytes>) {
+        self.queue(Message::Text(text.into()));
+    }
+}
+
+impl Client {
+    // Queue a message that belongs to no stream (e.g. a response)
+    fn send(&self, payload: &str) {
+        self.state.lock().unwrap().queue_text(payload);
+    }
+}
+
+// The numbering of a stream, the same for every client
+// Every candle of a stream is numbered from 1, so a client can resume the stream after a disconnection
+#[derive(Default)]
+struct StreamLog {
+    // Number of the last message
+    seq: u64,
+    // The last messages, bounded by their number and their size
+    messages: VecDeque<(u64, Arc<str>)>,
+    bytes: usize,
+}
+
+// What a client missed since a number
+#[derive(Debug, PartialEq)]
+enum Missed {
+    Messages(Vec<String>),
+    // Some of them are not kept anymore
+    Gap,
+    // The number was not given yet
+    Unknown,
+}
+
+impl StreamLog {
+    // Number a message and keep it for a resume
+    fn push(&mut self, payload: Arc<str>, (max_messages, max_bytes): (usize, usize)) -> String {
+        self.seq += 1;
+        let message = numbered(self.seq, &payload);
+
+        self.bytes += payload.len();
+        self.messages.push_back((self.seq, payload));
+        while self.messages.len() > max_messages || self.bytes > max_bytes {
+            let Some((_, dropped)) = self.messages.pop_front() else {
                 break;
+            };
+            self.bytes -= dropped.len();
+        }
+
+        message
+    }
+
+    // The messages from a number (included) to the last one
+    fn missed_messages(&self, from_seq: u64) -> Missed {
+        // Nothing missed
+        if from_seq == self.seq + 1 {
+            return Missed::Messages(Vec::new());
+        }
+        if from_seq > self.seq {
+            return Missed::Unknown;
+        }
+
+        match self.messages.front() {
+            Some((oldest, _)) if from_seq >= *oldest => Missed::Messages(self.messages.iter()
+                .filter(|(seq, _)| *seq >= from_seq)
+                .map(|(seq, payload)| numbered(*seq, payload))
+                .collect()),
+            _ => Missed::Gap,
+        }
+    }
+}
+
+// Add the number of a message to its payload (a JSON object)
+fn numbered(seq: u64, payload: &str) -> String {
+    format!("{{\"seq\":{},{}", seq, &payload[1..])
+}
+
+pub static CLIENTS: Lazy<Mutex<Vec<Arc<Client>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+static STREAMS: Lazy<std::sync::Mutex<HashMap<StreamKey, StreamLog>>> = Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+// Identifies this run of the service, the numbers of the streams start over with a new one
+// Random, so a number of a previous run is never taken for one of this run
+static EPOCH: Lazy<String> = Lazy::new(|| {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).expect("Unable to get random bytes");
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+});
+
+// Limits of the replay log of each stream
+static REPLAY_MESSAGES: AtomicUsize = AtomicUsize::new(0);
+static REPLAY_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+// Size of the queue of each connection, and how long writing a message can take
+static CLIENT_QUEUE: AtomicUsize = AtomicUsize::new(1);
+static SEND_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(0);
+
+// How long a new connection can take to subscribe or resume
+// Before it is subscribed to every stream
+static RESUME_WINDOW_SECS: AtomicU64 = AtomicU64::new(0);
+
+// Requests a client can send on the intra websocket
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum ClientRequest {
+    // The closed candles of a symbol and timerange opened before a time (ms), the last ones if no time is given
+    // The next page is asked with the `next_before` of the response
+    History {
+        symbol: String,
+        timerange: String,
+        limit: Option<usize>,
+        before: Option<i64>,
+    },
+    // Receive the candles of a symbol and timerange, every one of them if not given
+    // The candles in progress are sent right away
+    Subscribe {
+        symbol: Option<String>,
+        timerange: Option<String>,
+    },
+    // Stop receiving the candles of a symbol and timerange, every one of them if not given
+    Unsubscribe {
+        symbol: Option<String>,
+        timerange: Option<String>,
+    },
+    // Subscribe to a stream again after a disconnection, from the number following the last message received
+    // The epoch is the one given when connecting, the numbers of another one mean nothing
+    Resume {
+        epoch: String,
+        symbol: String,
+        timerange: String,
+        from_seq: u64,
+    },
+}
+
+// Notified when the provider connection has to be rebuilt
+// (e.g. the config changed and we need new streams)
+pub static RECONNECT: Lazy<Notify> = Lazy::new(Notify::new);
+
+// Returns the connection errors, the supervisor reconnects with its backoff
+pub async fn connect_to_provider_websocket() -> Result<(), tungstenite::Error> {
+    let mut shutdown = shutdown::subscribe();
+
+    loop {
+        // Load the configuration
+        // To create the WebSocket connection
+        // It is loaded at each (re)connection so config changes are taken into account
+        let config = CONFIG.get().unwrap().lock().await.clone();
+        let url = providers::general::build_stream_url(&config.stream.provider, &config.stream.url, &config.params.symbols, &config.stream.stream_type);
+
+        // Connect to the WebSocket stream
+        let (provider_ws_stream, _) = connect_async(&url).await
+            .inspect_err(|e| error!(error = %e, url, "Unable to connect to the provider"))?;
+
+        let (mut provider_write, mut provider_read) = provider_ws_stream.split();
+        METRICS.provider_connected.set(1);
+        METRICS.provider_connected_at.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+
+        // Mark the provider as disconnected however we leave the loop
+        let _connected = ConnectedGuard;
+
+        // Wait for messages from the WebSocket stream
+        // and get the candle data
+        // Or handle ping/pong messages
+        // Stop reading if we are asked to reconnect
+        loop {
+            let message = tokio::select! {
+                message = provider_read.next() => message,
+                _ = RECONNECT.notified() => {
+                    info!("Reconnecting to the provider");
+                    let _ = provider_write.send(Message::Close(None)).await;
+                    break;
+                },
+                // Stop the ingestion when the service is shutting down
+                _ = shutdown::wait(&mut shutdown) => {
+                    let _ = provider_write.send(Message::Close(None)).await;
+                    return Ok(());
+                }
+            };
+
+            match message {
+                Some(Ok(Message::Text(text))) => {
+                    // One span per message
+                    // It covers the parsing, the aggregation, the broadcast and the db writes
+                    let span = debug_span!("provider_message", provider = %config.stream.provider);
+                    handle_provider_message(&text, &config.stream.provider, config.params.decimal).instrument(span).await;
+                },
+                Some(Ok(Message::Ping(ping))) => {
+                    // Handle ping messages
+                    provider_write.send(Message::Pong(ping)).await
+                        .inspect_err(|e| error!(error = %e, "Unable to answer the provider ping"))?;
+                },
+                Some(Ok(_)) => (),
+                Some(Err(e)) => {
+                    error!(error = %e, "Provider websocket error");
+                    return Err(e);
+                }
+                None => return Ok(()),
             }
         }
     }
 }
 
+async fn handle_provider_message(text: &str, provider: &str, decimal: bool) {
+    METRICS.messages_received.inc(provider);
+    recorder::record(text);
+
+    let message = {
+        let _parse = debug_span!("parse").entered();
+        providers::general::parse_candle(text, provider, decimal)
+    };
+
+    match message {
+        Ok(ProviderMessage::Kline(candle, exact)) => proceed_data(candle, exact.map(|exact| *exact)).await,
+        Ok(ProviderMessage::SubscriptionAck { id }) => debug!(provider, ?id, "Subscription acknowledged"),
+        Ok(ProviderMessage::Error { code, message }) => {
+            METRICS.provider_errors.inc(provider);
+            warn!(provider, ?code, message, "Provider error");
+        },
+        Ok(ProviderMessage::Unknown(event)) => {
+            METRICS.unknown_messages.inc(provider);
+            debug!(provider, event, "Unknown provider message");
+        },
+        Err(e) => {
+            METRICS.parse_failures.inc(provider);
+            warn!(provider, error = %e, "Unable to parse provider message");
+        }
+    }
+}
+
+// Reset the provider connection status when dropped
+struct ConnectedGuard;
+
+impl Drop for ConnectedGuard {
+    fn drop(&mut self) {
+        METRICS.provider_connected.set(0);
+    }
+}
+
 pub async fn connect_to_intra_websocket() {
     // Load the url
     let url = common::WEBSOCKET_URL.as_str();
@@ -56,75 +311,506 @@ pub async fn connect_to_intra_websocket() {
         .await
         .expect("Unable to bind TCP listener");
 
+    let mut shutdown = shutdown::subscribe();
+
     // Start the WebSocket server
     // and accept incoming WebSocket connections
-    while let Ok((stream, _)) = listener.accept().await {
-        let ws_stream = accept_async(stream)
-            .await
-            .expect("Error during WebSocket handshake");
+    // Until the service is shutting down
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(_) => break,
+            },
+            _ = shutdown::wait(&mut shutdown) => break,
+        };
 
-        let (write, mut read) = ws_stream.split();
+        // Each client is handled in its own task
+        // So one client doesn't block the others
+        tokio::spawn(handle_intra_client(stream));
+    }
+}
 
-        // Add the new client to the list of clients
-        // Use an Arc and Mutex to share the client between tasks
-        // and ensure thread safety
-        let client = Arc::new(Mutex::new(write));
-        add_client(client.clone()).await;
+async fn handle_intra_client(stream: TcpStream) {
+    let ws_stream = match accept_async(stream).await {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => {
+            warn!(error = %e, "Error during WebSocket handshake");
+            return;
+        }
+    };
 
-        // Handle incoming messages from the WebSocket client
-        while let Some(message) = read.next().await {
-            match message {
-                Ok(Message::Text(_)) => {
-                   let mut write = client.lock().await;
-                    write.send(Message::Text("You can't send messages to this server".into()))
-                        .await
-                        .expect("Error sending message");
+    let (write, mut read) = ws_stream.split();
 
-                    break;
-                }
-                Ok(Message::Close(_)) => {
-                    // Handle the close message
-                    break;
-                }
-                Err(e) => {
-                    println!("Error: {}", e);
-                    break;
-                }
-                _ => {
-                    let mut write = client.lock().await;
-                    write.send(Message::Text("You can't send messages to this server".into()))
-                        .await
-                        .expect("Error sending message");
+    // The epoch first, to resume the streams after a disconnection
+    let dropped = Arc::new(Notify::new());
+    let client = connect_client(write, dropped.clone()).await;
 
-                    break;
-                }
+    // A reconnecting client first resumes its streams, or subscribes to some of them
+    // Before anything is numbered, so the numbers continue where it stopped
+    let first = match tokio::time::timeout(resume_window(), read.next()).await {
+        Ok(Some(Ok(message))) => Some(message),
+        Ok(_) => return disconnect_client(&client).await,
+        Err(_) => None,
+    };
+
+    let chooses_streams = first.as_ref()
+        .and_then(|message| message.to_text().ok())
+        .and_then(|text| serde_json::from_str::<serde_json::Value>(text).ok())
+        .is_some_and(|request| matches!(request["op"].as_str(), Some("subscribe" | "unsubscribe" | "resume")));
+    if !chooses_streams {
+        let streams = match_streams(None, None).await;
+        subscribe(&client, streams).await;
+    }
+
+    // Then the requests of the client, the first message included
+    // The live candles keep being pushed while a request is answered
+    let mut messages = futures_util::stream::iter(first.map(Ok)).chain(read);
+    loop {
+        let message = tokio::select! {
+            message = messages.next() => message,
+            _ = dropped.notified() => break,
+        };
+        let Some(message) = message else {
+            break;
+        };
+
+        let message = match message {
+            Ok(message) => message,
+            Err(e) => {
+                warn!(error = %e, "Intra websocket client error");
+                break;
             }
-        }
+        };
 
-        // Remove the client from the list of clients
-        remove_client(client.clone()).await;
+        match message {
+            Message::Close(_) => break,
+            Message::Text(text) => handle_client_request(&client, &text).await,
+            Message::Ping(_) | Message::Pong(_) => (),
+            _ => {
+                client.send(&error_message("Only text requests are accepted"));
+                break;
+            }
+        }
     }
+
+    disconnect_client(&client).await;
 }
 
-// Add a new client to the list of clients
-// Separte function to avoid long locks
-pub async fn add_client(client: Arc<Mutex<SplitSink<WebSocketStream<TcpStream>, Message>>>) {
+// Add a client without any subscription, with the task writing its connection
+async fn connect_client(write: Sink, dropped: Arc<Notify>) -> Arc<Client> {
+    let (outbox, queue) = mpsc::channel(CLIENT_QUEUE.load(Ordering::Relaxed));
+    let client = Arc::new(Client {
+        state: std::sync::Mutex::new(ClientState {
+            subscriptions: HashSet::new(),
+            outbox: Some(outbox),
+            writer: None,
+        }),
+    });
+
+    {
+        let mut state = client.state.lock().unwrap();
+        state.queue_text(json!({ "type": "epoch", "id": *EPOCH }).to_string());
+        state.writer = Some(tokio::spawn(write_messages(write, queue, dropped)));
+    }
+
     let mut clients = CLIENTS.lock().await;
-    clients.push(client);
+    clients.push(client.clone());
+    METRICS.intra_clients.set(clients.len() as i64);
+
+    client
 }
 
-// Same thing as above for removing a client
-pub async fn remove_client(client: Arc<Mutex<SplitSink<WebSocketStream<TcpStream>, Message>>>) {
+async fn disconnect_client(client: &Arc<Client>) {
+    // Its writer ends with the queue
+    client.state.lock().unwrap().outbox = None;
+
     let mut clients = CLIENTS.lock().await;
-    clients.retain(|c| !Arc::ptr_eq(c, &client));
+    clients.retain(|c| !Arc::ptr_eq(c, client));
+    METRICS.intra_clients.set(clients.len() as i64);
+}
+
+// Write the queue of a connection
+// Until the queue is closed, or a message can't be written in time
+async fn write_messages(mut write: Sink, mut queue: mpsc::Receiver<Message>, dropped: Arc<Notify>) {
+    let timeout = send_timeout();
+
+    let written = async {
+        while let Some(message) = queue.recv().await {
+            match tokio::time::timeout(timeout, write.send(message)).await {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) => return Err(e.to_string()),
+                Err(_) => return Err(format!("not written within {:?}", timeout)),
+            }
+        }
+        Ok(())
+    }.await;
+
+    match written {
+        Ok(()) => {
+            let _ = tokio::time::timeout(timeout, write.close()).await;
+        },
+        Err(e) => warn!(error = %e, "Unable to send a message to a client, dropping its connection"),
+    }
+
+    dropped.notify_one();
 }
 
-// Send a message to all connected clients
-pub async fn send_message_to_clients(message: &String) {
+// Answer a request of a client
+// The response is sent to this client only
+async fn handle_client_request(client: &Client, text: &str) {
+    let request: ClientRequest = match serde_json::from_str(text) {
+        Ok(request) => request,
+        Err(e) => return client.send(&error_message(&format!("Invalid request: {}", e))),
+    };
+
+    match request {
+        ClientRequest::History { symbol, timerange, limit, before } => {
+            let response = history(symbol, timerange, limit, before).await;
+            client.send(&response)
+        }
+        ClientRequest::Subscribe { symbol, timerange } => {
+            let streams = match check_streams(symbol, timerange).await {
+                Ok(streams) => streams,
+                Err(message) => return client.send(&message),
+            };
+
+            subscribe(client, streams).await
+        }
+        ClientRequest::Unsubscribe { symbol, timerange } => {
+            let streams = match check_streams(symbol, timerange).await {
+                Ok(streams) => streams,
+                Err(message) => return client.send(&message),
+            };
+
+            // Queued with the lock held, so no candle of these streams follows the response
+            let mut state = client.state.lock().unwrap();
+            for stream in &streams {
+                state.subscriptions.remove(stream);
+            }
+            state.queue_text(streams_message("unsubscribed", &streams))
+        }
+        ClientRequest::Resume { epoch, symbol, timerange, from_seq } => {
+            resume(client, &epoch, symbol, timerange, from_seq).await
+        }
+    }
+}
+
+async fn history(symbol: String, timerange: String, limit: Option<usize>, before: Option<i64>) -> String {
+    let known_symbol = CONFIG.get().unwrap().lock().await.params.symbols.contains(&symbol);
+    if !known_symbol {
+        return error_message(&format!("Unknown symbol {}", symbol));
+    }
+    if !TIMERANGES.lock().await.contains(&timerange) {
+        return error_message(&format!("Unknown timerange {}", timerange));
+    }
+
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let candles = match database::history_page(&symbol, &timerange, before, limit).await {
+        Ok(candles) => candles,
+        Err(e) => {
+            warn!(symbol, timerange, error = %e, "Unable to load the history");
+            return error_message("Unable to load the history");
+        }
+    };
+
+    // A full page, there may be older candles
+    let next_before = if candles.len() == limit { candles.first().map(|candle| candle.open_time) } else { None };
+
+    json!({
+        "type": "history",
+        "symbol": symbol,
+        "timerange": timerange,
+        "candles": candles,
+        "next_before": next_before,
+    }).to_string()
+}
+
+// The configured streams of a symbol and timerange, every one of them if not given
+async fn match_streams(symbol: Option<&str>, timerange: Option<&str>) -> Vec<StreamKey> {
+    let symbols = CONFIG.get().unwrap().lock().await.params.symbols.clone();
+    let timeranges = TIMERANGES.lock().await.clone();
+
+    symbols.iter()
+        .filter(|s| symbol.is_none_or(|symbol| symbol == s.as_str()))
+        .flat_map(|s| timeranges.iter().map(move |t| (s.clone(), t.clone())))
+        .filter(|(_, t)| timerange.is_none_or(|timerange| timerange == t))
+        .collect()
+}
+
+// Same, but an unknown symbol or timerange is an error for the client
+async fn check_streams(symbol: Option<String>, timerange: Option<String>) -> Result<Vec<StreamKey>, String> {
+    let streams = match_streams(symbol.as_deref(), timerange.as_deref()).await;
+
+    if streams.is_empty() {
+        let message = match (symbol, timerange) {
+            (Some(symbol), Some(timerange)) => format!("Unknown symbol {} or timerange {}", symbol, timerange),
+            (Some(symbol), None) => format!("Unknown symbol {}", symbol),
+            (None, Some(timerange)) => format!("Unknown timerange {}", timerange),
+            (None, None) => "No symbol configured".to_string(),
+        };
+        return Err(error_message(&message));
+    }
+
+    Ok(streams)
+}
+
+
+// Add streams to the subscriptions of a client, then send their candles in progress
+// Done while the aggregator state is locked
+// So the client gets every update after the snapshot, and none before
+async fn subscribe(client: &Client, streams: Vec<StreamKey>) {
+    let candles = CANDLES.lock().await;
+    let mut state = client.state.lock().unwrap();
+    let logs = STREAMS.lock().unwrap();
+
+    // With the number of the last candle of each stream, the next one follows it
+    let seqs: Vec<(&StreamKey, u64)> = streams.iter()
+        .map(|stream| (stream, logs.get(stream).map_or(0, |log| log.seq)))
+        .collect();
+
+    let subscribed: Vec<_> = seqs.iter()
+        .map(|((symbol, timerange), seq)| json!({ "symbol": symbol, "timerange": timerange, "seq": seq }))
+        .collect();
+    state.queue_text(json!({ "type": "subscribed", "streams": subscribed }).to_string());
+
+    for (stream, seq) in &seqs {
+        if let Some(snapshot) = snapshot_message(&candles, stream, *seq) {
+            state.queue_text(snapshot);
+        }
+    }
+
+    state.subscriptions.extend(streams.iter().cloned());
+}
+
+// Subscribe a client to a stream from a number of this epoch
+// It gets the candles it missed with their number, or the snapshot of the stream if some of them are not kept anymore
+async fn resume(client: &Client, epoch: &str, symbol: String, timerange: String, from_seq: u64) {
+    if epoch != EPOCH.as_str() {
+        return client.send(&error_message(&format!("Unknown epoch {} (the service may have restarted), subscribe again", epoch)));
+    }
+
+    let stream = match check_streams(Some(symbol), Some(timerange)).await {
+        Ok(mut streams) => streams.remove(0),
+        Err(message) => return client.send(&message),
+    };
+    let (symbol, timerange) = &stream;
+
+    let candles = CANDLES.lock().await;
+    let mut state = client.state.lock().unwrap();
+
+    // The candles of both would be mixed up
+    if state.subscriptions.contains(&stream) {
+        return state.queue_text(error_message(&format!("Already subscribed to {} {}, unsubscribe first", symbol, timerange)));
+    }
+
+    let logs = STREAMS.lock().unwrap();
+    let (seq, missed) = match logs.get(&stream) {
+        Some(log) => (log.seq, log.missed_messages(from_seq)),
+        None => (0, StreamLog::default().missed_messages(from_seq)),
+    };
+
+    let resumed = |count: usize, snapshot: bool| json!({
+        "type": "resumed",
+        "symbol": symbol,
+        "timerange": timerange,
+        "from_seq": from_seq,
+        "count": count,
+        "snapshot": snapshot,
+    }).to_string();
+
+    match missed {
+        Missed::Messages(messages) => {
+            state.queue_text(resumed(messages.len(), false));
+            for message in messages {
+                state.queue_text(message);
+            }
+        },
+        Missed::Gap => {
+            state.queue_text(resumed(0, true));
+            if let Some(snapshot) = snapshot_message(&candles, &stream, seq) {
+                state.queue_text(snapshot);
+            }
+        },
+        Missed::Unknown => {
+            return state.queue_text(error_message(&format!("Unknown seq {} for {} {}, the last one is {}", from_seq, symbol, timerange, seq)));
+        },
+    }
+
+    debug!(symbol, timerange, from_seq, seq, "Stream resumed");
+    state.subscriptions.insert(stream.clone());
+}
+
+// The candle in progress of a stream, with the number of the last candle sent
+fn snapshot_message(candles: &HashMap<String, HashMap<String, CandleOrValue>>, (symbol, timerange): &StreamKey, seq: u64) -> Option<String> {
+    match candles.get(symbol).and_then(|entries| entries.get(timerange)) {
+        // Nothing to send until the first tick of the bucket
+        Some(CandleOrValue::Candle(candle)) if candle.open_time != 0 => Some(numbered(seq, &candle_message("snapshot", candle))),
+        _ => None,
+    }
+}
+
+// Build a candle message
+pub fn candle_message(kind: &str, candle: &Candle) -> String {
+    json!({
+        "type": kind,
+        "value": candle,
+    }).to_string()
+}
+
+fn streams_message(kind: &str, streams: &[StreamKey]) -> String {
+    let streams: Vec<_> = streams.iter()
+        .map(|(symbol, timerange)| json!({ "symbol": symbol, "timerange": timerange }))
+        .collect();
+
+    json!({ "type": kind, "streams": streams }).to_string()
+}
+
+fn error_message(message: &str) -> String {
+    json!({ "type": "error", "message": message }).to_string()
+}
+
+// Set the size of the replay log of each stream, and the limits of the connections
+pub fn configure(config: &WebsocketConfig) {
+    REPLAY_MESSAGES.store(config.replay_buffer, Ordering::Relaxed);
+    REPLAY_BYTES.store(config.replay_buffer_bytes, Ordering::Relaxed);
+    CLIENT_QUEUE.store(config.client_queue, Ordering::Relaxed);
+    SEND_TIMEOUT_SECS.store(config.send_timeout_secs, Ordering::Relaxed);
+    RESUME_WINDOW_SECS.store(config.resume_window_secs, Ordering::Relaxed);
+}
+
+fn replay_limits() -> (usize, usize) {
+    (REPLAY_MESSAGES.load(Ordering::Relaxed), REPLAY_BYTES.load(Ordering::Relaxed))
+}
+
+fn send_timeout() -> Duration {
+    Duration::from_secs(SEND_TIMEOUT_SECS.load(Ordering::Relaxed))
+}
+
+fn resume_window() -> Duration {
+    Duration::from_secs(RESUME_WINDOW_SECS.load(Ordering::Relaxed))
+}
+
+// Send a close frame to every client
+// Used when the service is shutting down
+pub async fn close_clients() {
+    let writers: Vec<JoinHandle<()>> = {
+        let clients = CLIENTS.lock().await;
+
+        clients.iter()
+            .filter_map(|client| {
+                let mut state = client.state.lock().unwrap();
+                let frame = CloseFrame {
+                    code: CloseCode::Away,
+                    reason: "Server shutting down".into(),
+                };
+
+                // After the messages already queued, the writer ends with the queue
+                if let Some(outbox) = state.outbox.take() {
+                    let _ = outbox.try_send(Message::Close(Some(frame)));
+                }
+                state.writer.take()
+            })
+            .collect()
+    };
+
+    // A client that doesn't read is not waited for
+    let _ = tokio::time::timeout(send_timeout(), futures_util::future::join_all(writers)).await;
+}
+
+// Send a message that belongs to no stream to every client (e.g. the status of a feed)
+pub async fn send_message_to_clients(message: &str) {
+    let message = Utf8Bytes::from(message);
+
+    for client in CLIENTS.lock().await.iter() {
+        client.state.lock().unwrap().queue_text(message.clone());
+    }
+}
+
+// Number a message of a stream, then queue it for the clients subscribed to it
+// Called while the aggregator state is locked, a client is never waited for
+pub async fn send_message_to_subscribers(symbol: &str, timerange: &str, message: &str) {
+    let stream = (symbol.to_string(), timerange.to_string());
     let clients = CLIENTS.lock().await;
 
+    // Numbered once, the same for every client
+    let message = STREAMS.lock().unwrap()
+        .entry(stream.clone())
+        .or_default()
+        .push(message.into(), replay_limits());
+    let message = Utf8Bytes::from(message);
+
     for client in clients.iter() {
-        let mut write = client.lock().await;
-        write.send(Message::Text(message.into())).await.expect("Error sending message");
+        let mut state = client.state.lock().unwrap();
+        if state.subscriptions.contains(&stream) {
+            state.queue_text(message.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream_with(messages: u64, limits: (usize, usize)) -> StreamLog {
+        let mut log = StreamLog::default();
+        for i in 1..=messages {
+            log.push(format!("{{\"type\":\"candle\",\"value\":{}}}", i).into(), limits);
+        }
+
+        log
+    }
+
+    #[test]
+    fn missed_messages_are_the_ones_from_the_number() {
+        let log = stream_with(10, (100, usize::MAX));
+
+        assert_eq!(log.missed_messages(8), Missed::Messages(vec![
+            r#"{"seq":8,"type":"candle","value":8}"#.to_string(),
+            r#"{"seq":9,"type":"candle","value":9}"#.to_string(),
+            r#"{"seq":10,"type":"candle","value":10}"#.to_string(),
+        ]));
+
+        // Up to date
+        assert_eq!(log.missed_messages(11), Missed::Messages(Vec::new()));
+        assert!(matches!(log.missed_messages(1), Missed::Messages(messages) if messages.len() == 10));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn dropped_messages_are_a_gap() {
+        // Only the last 5 messages are kept
+        let log = stream_with(10, (5, usize::MAX));
+
+        assert!(matches!(log.missed_messages(6), Missed::Messages(messages) if messages.len() == 5));
+        assert_eq!(log.missed_messages(5), Missed::Gap);
+        assert_eq!(log.missed_messages(1), Missed::Gap);
+    }
+
+    #[test]
+    fn numbers_not_given_yet_are_unknown() {
+        let log = stream_with(10, (100, usize::MAX));
+        assert_eq!(log.missed_messages(12), Missed::Unknown);
+
+        // A stream without any candle yet
+        let empty = StreamLog::default();
+        assert_eq!(empty.missed_messages(1), Missed::Messages(Vec::new()));
+        assert_eq!(empty.missed_messages(5), Missed::Unknown);
+    }
+
+    #[test]
+    fn replay_log_is_bounded_by_size() {
+        // Each payload is 30 bytes or so, only 3 of them fit
+        let size = r#"{"type":"candle","value":10}"#.len();
+        let log = stream_with(10, (100, size * 3));
+
+        assert!(log.bytes <= size * 3);
+        assert_eq!(log.messages.len(), 3);
+        assert!(matches!(log.missed_messages(8), Missed::Messages(messages) if messages.len() == 3));
+        assert_eq!(log.missed_messages(7), Missed::Gap);
+    }
+
+    #[test]
+    fn numbered_message_keeps_the_payload() {
+        assert_eq!(numbered(42, r#"{"type":"status"}"#), r#"{"seq":42,"type":"status"}"#);
+    }
+}
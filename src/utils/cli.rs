@@ -0,0 +1,153 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use super::config::DEFAULT_CONFIG_PATH;
+use super::export::ExportFormat;
+use super::layers::LayerOptions;
+
+// Command line arguments of the core service
+#[derive(Debug, Parser)]
+#[command(name = "core", about = "ClusterX market data core")]
+pub struct Cli {
+    /// Path of the config file (optional if everything comes from the environment)
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Symbols to stream, comma separated (overrides params.symbols)
+    #[arg(long, global = true)]
+    pub symbols: Option<String>,
+
+    /// Provider stream url (overrides stream.url)
+    #[arg(long, global = true)]
+    pub url: Option<String>,
+
+    /// Override any config value, e.g. --set stream.type=kline_1m
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_key_value, global = true)]
+    pub overrides: Vec<(String, String)>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Feed recorded provider frames through the pipeline
+    Replay {
+        /// File written by the recorder ([recorder] path)
+        #[arg(long)]
+        file: PathBuf,
+        /// Speed factor compared to the recording (2 = twice as fast)
+        #[arg(long, default_value_t = 1.0, value_parser = parse_speed)]
+        speed: f64,
+        /// Replay as fast as possible, ignoring the recorded timing
+        #[arg(long, conflicts_with = "speed")]
+        fast: bool,
+        /// Write the closed candles as JSON lines to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Save the candles to the database
+        #[arg(long)]
+        persist: bool,
+    },
+    /// Import Binance kline archives (data.binance.vision) into the database
+    Import {
+        /// 1m kline CSV or ZIP files, or directories containing them
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Symbol of every file (taken from the file names otherwise)
+        #[arg(long)]
+        symbol: Option<String>,
+        /// Candles written per COPY
+        #[arg(long, default_value_t = 10_000)]
+        batch_size: usize,
+    },
+    /// Apply the pending database migrations
+    Migrate {
+        /// Only list the pending migrations
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Export the saved candles of a symbol
+    Export {
+        /// Symbol of the candles, e.g. BTCUSDT
+        #[arg(long)]
+        symbol: String,
+        /// Timerange of the candles, e.g. 1h
+        #[arg(long)]
+        timerange: String,
+        /// First open time (included), e.g. 2024-01-01 or 2024-01-01T12:00:00Z
+        #[arg(long, value_parser = parse_time)]
+        from: Option<DateTime<Utc>>,
+        /// Last open time (excluded)
+        #[arg(long, value_parser = parse_time)]
+        to: Option<DateTime<Utc>>,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the effective config and where each value comes from
+    Print,
+}
+
+impl Cli {
+    // The layers used to build the config
+    // The specific flags are applied after the generic --set ones
+    pub fn layer_options(&self) -> LayerOptions {
+        let mut overrides = self.overrides.clone();
+
+        if let Some(symbols) = &self.symbols {
+            overrides.push(("params.symbols".to_string(), symbols.clone()));
+        }
+        if let Some(url) = &self.url {
+            overrides.push(("stream.url".to_string(), url.clone()));
+        }
+
+        LayerOptions {
+            path: self.config.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH)),
+            // The default file is optional, but a file given explicitly must exist
+            path_required: self.config.is_some(),
+            overrides,
+        }
+    }
+}
+
+fn parse_key_value(raw: &str) -> Result<(String, String), String> {
+    let (key, value) = raw.split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got {:?}", raw))?;
+
+    Ok((key.trim().to_string(), value.to_string()))
+}
+
+// The replay waits the recorded delays divided by the speed
+fn parse_speed(raw: &str) -> Result<f64, String> {
+    raw.parse::<f64>().ok()
+        .filter(|speed| speed.is_finite() && *speed > 0.0)
+        .ok_or_else(|| format!("expected a positive number, got {:?}", raw))
+}
+
+// A date (midnight UTC), a RFC 3339 time, or a timestamp in milliseconds
+fn parse_time(raw: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(raw) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(raw, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+
+    raw.parse::<i64>().ok()
+        .and_then(DateTime::from_timestamp_millis)
+        .ok_or_else(|| format!("expected a date, a RFC 3339 time or a timestamp in milliseconds, got {:?}", raw))
+}
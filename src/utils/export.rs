@@ -0,0 +1,300 @@
+use crate::handler::candle::timerange_duration_ms;
+use crate::server::database::stream_candles;
+use crate::server::store::{StoreError, StoredCandle};
+
+use arrow_array::{ArrayRef, BooleanArray, Decimal128Array, Float64Array, RecordBatch, StringArray, TimestampMillisecondArray};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use futures_util::StreamExt;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use rust_decimal::Decimal;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+    Parquet,
+}
+
+pub struct ExportOptions {
+    pub symbol: String,
+    pub timerange: String,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub format: ExportFormat,
+    // Stdout if not set
+    pub output: Option<PathBuf>,
+    // Decimal columns in parquet, instead of floats
+    pub decimal: bool,
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    UnsupportedTimerange(String),
+    Database(StoreError),
+    Write(std::io::Error),
+    Parquet(ParquetError),
+    // A value too large for the parquet decimal columns
+    DecimalOutOfRange(Decimal),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::UnsupportedTimerange(timerange) => write!(f, "Unsupported timerange {}", timerange),
+            ExportError::Database(e) => write!(f, "Unable to read the candles: {}", e),
+            ExportError::Write(e) => write!(f, "Unable to write the candles: {}", e),
+            ExportError::Parquet(e) => write!(f, "Unable to write the parquet file: {}", e),
+            ExportError::DecimalOutOfRange(value) => write!(f, "{} doesn't fit in a decimal({}, {}) column", value, PARQUET_DECIMAL_PRECISION, PARQUET_DECIMAL_SCALE),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExportError::UnsupportedTimerange(_) | ExportError::DecimalOutOfRange(_) => None,
+            ExportError::Database(e) => Some(e),
+            ExportError::Write(e) => Some(e),
+            ExportError::Parquet(e) => Some(e),
+        }
+    }
+}
+
+impl From<StoreError> for ExportError {
+    fn from(e: StoreError) -> Self {
+        ExportError::Database(e)
+    }
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(e: std::io::Error) -> Self {
+        ExportError::Write(e)
+    }
+}
+
+impl From<ParquetError> for ExportError {
+    fn from(e: ParquetError) -> Self {
+        ExportError::Parquet(e)
+    }
+}
+
+// Write the candles of a symbol and timerange to a file (or stdout)
+// Returns the number of candles written
+pub async fn export(options: &ExportOptions) -> Result<u64, ExportError> {
+    if timerange_duration_ms(&options.timerange).is_none() {
+        return Err(ExportError::UnsupportedTimerange(options.timerange.clone()));
+    }
+
+    let output: Box<dyn Write + Send> = match &options.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(std::io::stdout()),
+    };
+    let mut writer = CandleWriter::new(options.format, options.decimal, BufWriter::new(output))?;
+
+    let mut candles = stream_candles(&options.symbol, &options.timerange, options.from, options.to).await?;
+
+    let mut count = 0;
+    while let Some(candle) = candles.next().await {
+        writer.write(candle?)?;
+        count += 1;
+    }
+
+    writer.finish()?;
+
+    Ok(count)
+}
+
+// Rows per parquet record batch
+const PARQUET_BATCH_SIZE: usize = 8192;
+
+// Decimal type of the parquet values in decimal mode
+// The exchange values and their products fit in 18 decimals
+const PARQUET_DECIMAL_PRECISION: u8 = 38;
+const PARQUET_DECIMAL_SCALE: i8 = 18;
+
+type Output = BufWriter<Box<dyn Write + Send>>;
+
+enum CandleWriter {
+    Csv(Output),
+    Jsonl(Output),
+    // The rows are buffered to be written by batch
+    Parquet { writer: Box<ArrowWriter<Output>>, schema: SchemaRef, rows: Vec<StoredCandle> },
+}
+
+// The values of a candle as text
+// The exact ones when we have them, so the decimal mode keeps them as saved
+fn value_strings(candle: &StoredCandle) -> [String; 6] {
+    match &candle.exact {
+        Some(exact) => [exact.open, exact.high, exact.low, exact.price, exact.volume, exact.usdt_volume].map(|value| value.to_string()),
+        None => [candle.open, candle.high, candle.low, candle.close, candle.volume, candle.usdt_volume].map(|value| value.to_string()),
+    }
+}
+
+impl CandleWriter {
+    fn new(format: ExportFormat, decimal: bool, mut output: Output) -> Result<Self, ExportError> {
+        Ok(match format {
+            ExportFormat::Csv => {
+                writeln!(output, "symbol,timerange,open_time,close_time,open,high,low,close,volume,usdt_volume,partial")?;
+                CandleWriter::Csv(output)
+            },
+            ExportFormat::Jsonl => CandleWriter::Jsonl(output),
+            ExportFormat::Parquet => {
+                let schema = parquet_schema(decimal);
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::ZSTD(ZstdLevel::default()))
+                    .build();
+                let writer = Box::new(ArrowWriter::try_new(output, schema.clone(), Some(properties))?);
+
+                CandleWriter::Parquet { writer, schema, rows: Vec::with_capacity(PARQUET_BATCH_SIZE) }
+            },
+        })
+    }
+
+    fn write(&mut self, candle: StoredCandle) -> Result<(), ExportError> {
+        match self {
+            CandleWriter::Csv(output) => {
+                let [open, high, low, close, volume, usdt_volume] = value_strings(&candle);
+                writeln!(output, "{},{},{},{},{},{},{},{},{},{},{}", candle.symbol, candle.timerange, candle.open_time, candle.close_time, open, high, low, close, volume, usdt_volume, candle.partial)?;
+            },
+            CandleWriter::Jsonl(output) => {
+                // The numbers are written as is, serde_json would round them to f64
+                let [open, high, low, close, volume, usdt_volume] = value_strings(&candle);
+                let symbol = serde_json::to_string(&candle.symbol).map_err(std::io::Error::from)?;
+                let timerange = serde_json::to_string(&candle.timerange).map_err(std::io::Error::from)?;
+                writeln!(output, r#"{{"symbol":{},"timerange":{},"open_time":{},"close_time":{},"open":{},"high":{},"low":{},"close":{},"volume":{},"usdt_volume":{},"partial":{}}}"#,
+                    symbol, timerange, candle.open_time, candle.close_time, open, high, low, close, volume, usdt_volume, candle.partial)?;
+            },
+            CandleWriter::Parquet { writer, schema, rows } => {
+                rows.push(candle);
+                if rows.len() >= PARQUET_BATCH_SIZE {
+                    writer.write(&record_batch(schema, rows)?)?;
+                    rows.clear();
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), ExportError> {
+        match self {
+            CandleWriter::Csv(mut output) | CandleWriter::Jsonl(mut output) => output.flush()?,
+            CandleWriter::Parquet { mut writer, schema, rows } => {
+                if !rows.is_empty() {
+                    writer.write(&record_batch(&schema, &rows)?)?;
+                }
+                writer.into_inner()?.flush()?;
+            },
+        }
+
+        Ok(())
+    }
+}
+
+fn parquet_schema(decimal: bool) -> SchemaRef {
+    let timestamp = DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()));
+    let number = if decimal { DataType::Decimal128(PARQUET_DECIMAL_PRECISION, PARQUET_DECIMAL_SCALE) } else { DataType::Float64 };
+
+    Arc::new(Schema::new(vec![
+        Field::new("symbol", DataType::Utf8, false),
+        Field::new("timerange", DataType::Utf8, false),
+        Field::new("open_time", timestamp.clone(), false),
+        Field::new("close_time", timestamp, false),
+        Field::new("open", number.clone(), false),
+        Field::new("high", number.clone(), false),
+        Field::new("low", number.clone(), false),
+        Field::new("close", number.clone(), false),
+        Field::new("volume", number.clone(), false),
+        Field::new("usdt_volume", number.clone(), false),
+        Field::new("partial", DataType::Boolean, false),
+    ]))
+}
+
+// The value of a decimal column, as an integer of 10^-18
+// Decimal::rescale would silently keep a smaller scale for the large values
+fn parquet_decimal(value: Decimal) -> Result<i128, ExportError> {
+    let value = value.round_dp(PARQUET_DECIMAL_SCALE as u32);
+    let max = 10_i128.pow(PARQUET_DECIMAL_PRECISION as u32);
+
+    10_i128.checked_pow(PARQUET_DECIMAL_SCALE as u32 - value.scale())
+        .and_then(|factor| value.mantissa().checked_mul(factor))
+        .filter(|mantissa| mantissa.abs() < max)
+        .ok_or(ExportError::DecimalOutOfRange(value))
+}
+
+fn record_batch(schema: &SchemaRef, rows: &[StoredCandle]) -> Result<RecordBatch, ExportError> {
+    let timestamps = |time: fn(&StoredCandle) -> i64| -> ArrayRef {
+        Arc::new(TimestampMillisecondArray::from_iter_values(rows.iter().map(time)).with_timezone("UTC"))
+    };
+    let decimal = matches!(schema.field_with_name("open").map(|field| field.data_type()), Ok(DataType::Decimal128(..)));
+    // The exact value if known, the float otherwise
+    let numbers = |exact: fn(&StoredCandle) -> Option<Decimal>, float: fn(&StoredCandle) -> f64| -> Result<ArrayRef, ExportError> {
+        if !decimal {
+            return Ok(Arc::new(Float64Array::from_iter_values(rows.iter().map(float))));
+        }
+
+        let values = rows.iter()
+            .map(|candle| parquet_decimal(exact(candle).or_else(|| Decimal::try_from(float(candle)).ok()).unwrap_or_default()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let array = Decimal128Array::from_iter_values(values)
+            .with_precision_and_scale(PARQUET_DECIMAL_PRECISION, PARQUET_DECIMAL_SCALE)
+            .map_err(|e| ExportError::Parquet(e.into()))?;
+
+        Ok(Arc::new(array))
+    };
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(rows.iter().map(|c| c.symbol.as_str()))),
+        Arc::new(StringArray::from_iter_values(rows.iter().map(|c| c.timerange.as_str()))),
+        timestamps(|c| c.open_time),
+        timestamps(|c| c.close_time),
+        numbers(|c| c.exact.as_ref().map(|e| e.open), |c| c.open)?,
+        numbers(|c| c.exact.as_ref().map(|e| e.high), |c| c.high)?,
+        numbers(|c| c.exact.as_ref().map(|e| e.low), |c| c.low)?,
+        numbers(|c| c.exact.as_ref().map(|e| e.price), |c| c.close)?,
+        numbers(|c| c.exact.as_ref().map(|e| e.volume), |c| c.volume)?,
+        numbers(|c| c.exact.as_ref().map(|e| e.usdt_volume), |c| c.usdt_volume)?,
+        Arc::new(rows.iter().map(|c| Some(c.partial)).collect::<BooleanArray>()),
+    ];
+
+    RecordBatch::try_new(schema.clone(), columns).map_err(|e| ExportError::Parquet(e.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn parquet_decimals_keep_their_scale() {
+        let decimal = |value: &str| parquet_decimal(Decimal::from_str(value).unwrap()).unwrap();
+
+        assert_eq!(decimal("1.5"), 1_500_000_000_000_000_000);
+        assert_eq!(decimal("-0.00000001"), -10_000_000_000);
+        // Too large for Decimal::rescale to keep 18 decimals
+        assert_eq!(decimal("123456789012.5"), 123_456_789_012_500_000_000_000_000_000);
+        assert_eq!(decimal("99999999999999999999.99999999"), 99_999_999_999_999_999_999_999_999_990_000_000_000);
+        // More decimals than the column
+        assert_eq!(decimal("0.1234567890123456789"), 123_456_789_012_345_679);
+    }
+
+    #[test]
+    fn parquet_decimals_out_of_range_are_an_error() {
+        // 20 digits before the point, 38 with the scale
+        let value = Decimal::from_str("100000000000000000000").unwrap();
+
+        assert!(matches!(parquet_decimal(value), Err(ExportError::DecimalOutOfRange(_))));
+        assert!(matches!(parquet_decimal(-value), Err(ExportError::DecimalOutOfRange(_))));
+        assert!(matches!(parquet_decimal(Decimal::MAX), Err(ExportError::DecimalOutOfRange(_))));
+    }
+}
@@ -0,0 +1,26 @@
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+use super::config::{LogFormat, LoggingConfig};
+
+// Install the global logger
+// RUST_LOG takes precedence over the configured level
+pub fn init_logging(config: &LoggingConfig) {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(&config.level));
+
+    // Log the duration of each span when it closes
+    // So we can follow the latency of a tick through the pipeline
+    let span_events = if config.span_timings { FmtSpan::CLOSE } else { FmtSpan::NONE };
+
+    // On stderr, so the commands writing data to stdout (replay, export) can be piped
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(span_events)
+        .with_writer(std::io::stderr);
+
+    match config.format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().with_current_span(true).with_span_list(true).init(),
+    }
+}
@@ -0,0 +1,175 @@
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+
+// Counter with a single label (e.g. per symbol)
+#[derive(Default)]
+pub struct CounterVec(Mutex<BTreeMap<String, u64>>);
+
+impl CounterVec {
+    pub fn inc(&self, label: &str) {
+        let mut values = self.0.lock().unwrap();
+        *values.entry(label.to_string()).or_insert(0) += 1;
+    }
+
+    fn render(&self, name: &str, help: &str, label: &str, output: &mut String) {
+        let _ = writeln!(output, "# HELP {} {}", name, help);
+        let _ = writeln!(output, "# TYPE {} counter", name);
+        for (value_label, value) in self.0.lock().unwrap().iter() {
+            let _ = writeln!(output, "{}{{{}=\"{}\"}} {}", name, label, value_label, value);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn render(&self, name: &str, help: &str, output: &mut String) {
+        let _ = writeln!(output, "# HELP {} {}", name, help);
+        let _ = writeln!(output, "# TYPE {} gauge", name);
+        let _ = writeln!(output, "{} {}", name, self.get());
+    }
+}
+
+// Upper bounds (in seconds) of the latency buckets
+const LATENCY_BUCKETS: [f64; 10] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+// Histogram of durations
+#[derive(Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    // Sum in microseconds, to keep an integer
+    sum_us: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+
+        for (bucket, bound) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, name: &str, help: &str, output: &mut String) {
+        let _ = writeln!(output, "# HELP {} {}", name, help);
+        let _ = writeln!(output, "# TYPE {} histogram", name);
+        for (bucket, bound) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            let _ = writeln!(output, "{}_bucket{{le=\"{}\"}} {}", name, bound, bucket.load(Ordering::Relaxed));
+        }
+
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(output, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(output, "{}_sum {}", name, self.sum_us.load(Ordering::Relaxed) as f64 / 1_000_000.0);
+        let _ = writeln!(output, "{}_count {}", name, count);
+    }
+}
+
+// Every metric of the service
+#[derive(Default)]
+pub struct Metrics {
+    pub messages_received: CounterVec,
+    pub parse_failures: CounterVec,
+    pub unknown_messages: CounterVec,
+    pub provider_errors: CounterVec,
+    pub ticks_processed: CounterVec,
+    pub candles_closed: CounterVec,
+    pub validation_failures: CounterVec,
+    pub db_write_duration: Histogram,
+    pub db_write_failures: CounterVec,
+    // Failed writes kept to be retried
+    pub db_writes_pending: Gauge,
+    pub db_pool_waiting: Gauge,
+    pub db_pool_connections: Gauge,
+    pub db_pool_available: Gauge,
+    pub intra_clients: Gauge,
+    pub provider_connected: Gauge,
+    // Set once the last candles are loaded from the database
+    pub warmup_done: AtomicBool,
+    // When the provider websocket was last connected (ms)
+    pub provider_connected_at: AtomicI64,
+    // When each symbol was last updated (ms)
+    last_tick: Mutex<BTreeMap<String, i64>>,
+}
+
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+impl Metrics {
+    // Record a processed tick for the symbol
+    pub fn tick(&self, symbol: &str) {
+        self.ticks_processed.inc(symbol);
+        self.last_tick.lock().unwrap().insert(symbol.to_string(), Utc::now().timestamp_millis());
+    }
+
+    // When the symbol was last updated (ms)
+    pub fn last_tick(&self, symbol: &str) -> Option<i64> {
+        self.last_tick.lock().unwrap().get(symbol).copied()
+    }
+
+    // Time since the last tick of each symbol
+    pub fn since_last_tick(&self) -> BTreeMap<String, Duration> {
+        let now = Utc::now().timestamp_millis();
+
+        self.last_tick.lock().unwrap()
+            .iter()
+            .map(|(symbol, last)| (symbol.clone(), Duration::from_millis((now - last).max(0) as u64)))
+            .collect()
+    }
+
+    // Render every metric in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+
+        self.messages_received.render("clusterx_messages_received_total", "Messages received from the provider", "provider", &mut output);
+        self.parse_failures.render("clusterx_parse_failures_total", "Provider messages that could not be parsed", "provider", &mut output);
+        self.unknown_messages.render("clusterx_unknown_messages_total", "Provider messages of an unhandled type", "provider", &mut output);
+        self.provider_errors.render("clusterx_provider_errors_total", "Errors reported by the provider", "provider", &mut output);
+        self.ticks_processed.render("clusterx_ticks_processed_total", "Ticks processed by the aggregator", "symbol", &mut output);
+        self.candles_closed.render("clusterx_candles_closed_total", "Candles closed by the aggregator", "timerange", &mut output);
+        self.validation_failures.render("clusterx_validation_failures_total", "Candles rejected by the validation, per rule", "rule", &mut output);
+        self.db_write_duration.render("clusterx_db_write_duration_seconds", "Duration of the candle writes", &mut output);
+        self.db_write_failures.render("clusterx_db_write_failures_total", "Candle writes that failed, per timerange", "timerange", &mut output);
+        self.db_writes_pending.render("clusterx_db_writes_pending", "Candles waiting to be written again after a failure", &mut output);
+        self.db_pool_waiting.render("clusterx_db_pool_waiting", "Database operations waiting for a connection", &mut output);
+        self.db_pool_connections.render("clusterx_db_pool_connections", "Open connections of the database pool", &mut output);
+        self.db_pool_available.render("clusterx_db_pool_available", "Idle connections of the database pool", &mut output);
+        self.intra_clients.render("clusterx_intra_clients", "Connected intra websocket clients", &mut output);
+        self.provider_connected.render("clusterx_provider_connected", "1 if the provider websocket is connected", &mut output);
+
+        let name = "clusterx_seconds_since_last_tick";
+        let _ = writeln!(output, "# HELP {} Seconds since the last tick of the symbol", name);
+        let _ = writeln!(output, "# TYPE {} gauge", name);
+        for (symbol, elapsed) in self.since_last_tick() {
+            let _ = writeln!(output, "{}{{symbol=\"{}\"}} {}", name, symbol, elapsed.as_secs_f64());
+        }
+
+        output
+    }
+}
@@ -1 +1,7 @@
-pub mod config;
\ No newline at end of file
+pub mod cli;
+pub mod config;
+pub mod export;
+pub mod layers;
+pub mod logging;
+pub mod metrics;
+pub mod reload;
\ No newline at end of file
@@ -0,0 +1,202 @@
+use crate::CONFIG;
+use crate::handler::candle::{register_symbols, unregister_symbols};
+use crate::server::websocket::RECONNECT;
+use crate::utils::config::{self, Config, ConfigError};
+
+use common::TIMERANGES;
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+use toml::{Table, Value};
+use tracing::{error, info, warn};
+
+// How often we check if the config file changed
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// The fields applied to the live pipeline
+// The validation rules are read for every candle
+const LIVE_FIELDS: &[&str] = &["params.symbols", "params.timeranges", "stream", "validation"];
+
+// What changed between the running config and the new one
+#[derive(Debug, Default)]
+pub struct ConfigDiff {
+    pub new_symbols: Vec<String>,
+    pub removed_symbols: Vec<String>,
+    pub new_timeranges: Vec<String>,
+    pub removed_timeranges: Vec<String>,
+    pub stream_changed: bool,
+    // The other changed fields, only used after a restart
+    pub restart_required: Vec<String>,
+}
+
+impl ConfigDiff {
+    pub fn between(old: &Config, new: &Config) -> Self {
+        let new_symbols = new.params.symbols.iter()
+            .filter(|s| !old.params.symbols.contains(s))
+            .cloned()
+            .collect();
+        let removed_symbols = old.params.symbols.iter()
+            .filter(|s| !new.params.symbols.contains(s))
+            .cloned()
+            .collect();
+        // An empty list means the default timeranges
+        let old_timeranges = config::effective_timeranges(old);
+        let new_timeranges = config::effective_timeranges(new);
+        let removed_timeranges = old_timeranges.iter()
+            .filter(|t| !new_timeranges.contains(t))
+            .cloned()
+            .collect();
+        let new_timeranges = new_timeranges.into_iter()
+            .filter(|t| !old_timeranges.contains(t))
+            .collect();
+
+        ConfigDiff {
+            new_symbols,
+            removed_symbols,
+            new_timeranges,
+            removed_timeranges,
+            stream_changed: old.stream != new.stream,
+            restart_required: changed_fields(old, new).into_iter()
+                .filter(|field| !LIVE_FIELDS.iter().any(|live| field == live || field.starts_with(&format!("{}.", live))))
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.new_symbols.is_empty() && self.removed_symbols.is_empty() && self.new_timeranges.is_empty() && self.removed_timeranges.is_empty() && !self.stream_changed
+    }
+
+    // The provider connection only has to be rebuilt
+    // if the streams we are subscribed to changed
+    pub fn needs_reconnect(&self) -> bool {
+        self.stream_changed || !self.new_symbols.is_empty() || !self.removed_symbols.is_empty()
+    }
+}
+
+// The paths of the values that differ between two configs, e.g. http.address
+fn changed_fields(old: &Config, new: &Config) -> Vec<String> {
+    fn table(config: &Config) -> Table {
+        match Value::try_from(config) {
+            Ok(Value::Table(table)) => table,
+            _ => Table::new(),
+        }
+    }
+
+    fn compare(old: &Table, new: &Table, prefix: &str, changed: &mut Vec<String>) {
+        let keys = old.keys().chain(new.keys().filter(|key| !old.contains_key(*key)));
+
+        for key in keys {
+            let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+            match (old.get(key), new.get(key)) {
+                (Some(Value::Table(old)), Some(Value::Table(new))) => compare(old, new, &path, changed),
+                (old, new) if old != new => changed.push(path),
+                _ => (),
+            }
+        }
+    }
+
+    let mut changed = Vec::new();
+    compare(&table(old), &table(new), "", &mut changed);
+
+    changed
+}
+
+// Watch the config file and reload it
+// Either when it is modified or when we receive a SIGHUP
+pub async fn watch_config() {
+    let mut hangup = signal(SignalKind::hangup()).expect("Unable to listen to SIGHUP");
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    let mut last_modified = modified_time();
+
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => {
+                info!("SIGHUP received, reloading config");
+            },
+            _ = interval.tick() => {
+                let modified = modified_time();
+                if modified == last_modified {
+                    continue;
+                }
+
+                info!("Config file changed, reloading config");
+            }
+        }
+
+        last_modified = modified_time();
+
+        if let Err(e) = reload_config().await {
+            error!(error = %e, "Config not reloaded");
+        }
+    }
+}
+
+fn modified_time() -> Option<SystemTime> {
+    std::fs::metadata(config::config_path()).and_then(|m| m.modified()).ok()
+}
+
+// Parse the config file again and apply the changes to the live pipeline
+// The running config is kept if the new one is invalid
+pub async fn reload_config() -> Result<ConfigDiff, ConfigError> {
+    let options = config::LAYER_OPTIONS.get().ok_or(ConfigError::NotLoaded)?;
+    let new_config = config::build_config(options)?;
+
+    let config = CONFIG.get().ok_or(ConfigError::NotLoaded)?;
+    let mut running = config.lock().await;
+
+    let diff = ConfigDiff::between(&running, &new_config);
+
+    // Stored anyway, so they are used after a restart
+    if !diff.restart_required.is_empty() {
+        warn!(fields = ?diff.restart_required, "Config changes that need a restart to be applied");
+    }
+
+    if diff.is_empty() {
+        *running = new_config;
+        return Ok(diff);
+    }
+
+    info!(?diff, "Applying config changes");
+
+    // Update the timeranges first
+    // So the new symbols get every timerange
+    config::apply_timeranges(&new_config).await;
+    let timeranges = {
+        let timeranges = TIMERANGES.lock().await;
+        timeranges.clone()
+    };
+    register_symbols(&new_config.params.symbols, &timeranges).await;
+
+    *running = new_config;
+    drop(running);
+
+    // The removed ones stop being aggregated
+    unregister_symbols(&diff.removed_symbols, &diff.removed_timeranges).await;
+
+    // Rebuild the provider connection with the new streams
+    if diff.needs_reconnect() {
+        RECONNECT.notify_one();
+    }
+
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_that_need_a_restart_are_listed() {
+        let old = Config::default();
+        let mut new = old.clone();
+        new.params.symbols.push("BTCUSDT".to_string());
+        new.validation.max_price_jump_pct = 5.0;
+        new.http.address = "127.0.0.1:1".to_string();
+        new.params.decimal = true;
+
+        let diff = ConfigDiff::between(&old, &new);
+        assert_eq!(diff.new_symbols, vec!["BTCUSDT"]);
+        assert_eq!(diff.restart_required, vec!["http.address", "params.decimal"]);
+
+        assert!(ConfigDiff::between(&old, &old).restart_required.is_empty());
+    }
+}